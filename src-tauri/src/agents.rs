@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use crate::files::write_atomic_bytes;
+use crate::mcp::McpFormat;
+use crate::paths::{agents_path, expand_home};
+use crate::transform::CommandFormat;
+
+pub const AGENT_REGISTRY_VERSION: u32 = 3;
+
+/// Scope names that can never be used as agent ids.
+const RESERVED_AGENT_IDS: [&str; 1] = ["source"];
+
+/// MCP targets of the version 1 builtins.
+pub const LEGACY_MCP_TARGETS: [(&str, &str); 3] = [
+    ("codex", "mcp.json"),
+    ("gemini", "antigravity/mcp_config.json"),
+    ("claude", "mcp.json"),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentDefinition {
+    pub id: String,
+    pub display_name: String,
+    /// Agent config root. `~/` is expanded to HOME, other relative paths are
+    /// resolved against HOME as well.
+    pub root_dir: String,
+    /// Default target path per category, used when a category mapping has no
+    /// explicit target for this agent.
+    #[serde(default)]
+    pub default_targets: BTreeMap<String, String>,
+    /// Native MCP config format; inferred from the target extension if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcp_format: Option<McpFormat>,
+    /// Slash-command file format; Markdown if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_format: Option<CommandFormat>,
+}
+
+impl AgentDefinition {
+    pub fn root_path(&self) -> Result<PathBuf, String> {
+        expand_home(&self.root_dir)
+    }
+
+    pub fn default_target(&self, category: &str) -> String {
+        self.default_targets
+            .get(category)
+            .cloned()
+            .unwrap_or_else(|| category.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentRegistry {
+    pub version: u32,
+    pub agents: Vec<AgentDefinition>,
+}
+
+impl AgentRegistry {
+    pub fn find(&self, id: &str) -> Option<&AgentDefinition> {
+        self.agents.iter().find(|agent| agent.id == id)
+    }
+}
+
+fn builtin_agent(
+    id: &str,
+    display_name: &str,
+    root_dir: &str,
+    targets: [(&str, &str); 5],
+    mcp_format: McpFormat,
+    command_format: CommandFormat,
+) -> AgentDefinition {
+    AgentDefinition {
+        id: id.to_string(),
+        display_name: display_name.to_string(),
+        root_dir: root_dir.to_string(),
+        default_targets: targets
+            .iter()
+            .map(|(category, target)| (category.to_string(), target.to_string()))
+            .collect(),
+        mcp_format: Some(mcp_format),
+        command_format: Some(command_format),
+    }
+}
+
+pub fn default_agent_registry() -> AgentRegistry {
+    AgentRegistry {
+        version: AGENT_REGISTRY_VERSION,
+        agents: vec![
+            builtin_agent(
+                "codex",
+                "Codex",
+                "~/.codex",
+                [
+                    ("instructions", "AGENTS.md"),
+                    ("skills", "skills"),
+                    ("plugins", "plugins"),
+                    ("commands", "rules"),
+                    ("mcp", "config.toml"),
+                ],
+                McpFormat::Codex,
+                CommandFormat::Markdown,
+            ),
+            builtin_agent(
+                "gemini",
+                "Gemini",
+                "~/.gemini",
+                [
+                    ("instructions", "GEMINI.md"),
+                    ("skills", "skills"),
+                    ("plugins", "plugins"),
+                    ("commands", "commands"),
+                    ("mcp", "settings.json"),
+                ],
+                McpFormat::Gemini,
+                CommandFormat::Toml,
+            ),
+            builtin_agent(
+                "claude",
+                "Claude",
+                "~/.claude",
+                [
+                    ("instructions", "CLAUDE.md"),
+                    ("skills", "skills"),
+                    ("plugins", "plugins"),
+                    ("commands", "commands"),
+                    ("mcp", "mcp.json"),
+                ],
+                McpFormat::Claude,
+                CommandFormat::Markdown,
+            ),
+        ],
+    }
+}
+
+fn validate_agent_id(id: &str) -> Result<(), String> {
+    if id.trim().is_empty() {
+        return Err("Agent id cannot be empty".to_string());
+    }
+    if RESERVED_AGENT_IDS.contains(&id) {
+        return Err(format!("Agent id is reserved: {id}"));
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Agent id may only contain letters, digits, '-' and '_': {id}"
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_agent_registry(registry: &AgentRegistry) -> Result<(), String> {
+    if registry.version == 0 {
+        return Err("Agent registry version must be greater than 0".to_string());
+    }
+    if registry.agents.is_empty() {
+        return Err("Agent registry must define at least one agent".to_string());
+    }
+    let mut seen = HashSet::new();
+    for agent in &registry.agents {
+        validate_agent_id(&agent.id)?;
+        if !seen.insert(agent.id.as_str()) {
+            return Err(format!("Duplicate agent id: {}", agent.id));
+        }
+        if agent.root_dir.trim().is_empty() {
+            return Err(format!("Agent '{}' must define root_dir", agent.id));
+        }
+    }
+    Ok(())
+}
+
+pub fn save_agent_registry_inner(registry: &AgentRegistry) -> Result<(), String> {
+    validate_agent_registry(registry)?;
+    let path = agents_path()?;
+    let payload = serde_json::to_string_pretty(registry).map_err(|e| e.to_string())?;
+    write_atomic_bytes(&path, payload.as_bytes())
+}
+
+/// Older builtins copied one MCP file verbatim and commands byte-for-byte;
+/// point them at each agent's native MCP config and command format instead.
+/// Customized targets are left alone.
+fn upgrade_agent_registry(registry: &mut AgentRegistry) -> bool {
+    if registry.version >= AGENT_REGISTRY_VERSION {
+        return false;
+    }
+    let builtins = default_agent_registry();
+    for agent in &mut registry.agents {
+        let Some(builtin) = builtins.find(&agent.id) else {
+            continue;
+        };
+        let legacy = LEGACY_MCP_TARGETS
+            .iter()
+            .any(|(id, target)| *id == agent.id && agent.default_target("mcp") == *target);
+        if legacy {
+            agent
+                .default_targets
+                .insert("mcp".to_string(), builtin.default_target("mcp"));
+        }
+        if agent.mcp_format.is_none() {
+            agent.mcp_format = builtin.mcp_format;
+        }
+        if agent.command_format.is_none() {
+            agent.command_format = builtin.command_format;
+        }
+    }
+    registry.version = AGENT_REGISTRY_VERSION;
+    true
+}
+
+pub fn load_agent_registry() -> Result<AgentRegistry, String> {
+    let path = agents_path()?;
+    if !path.exists() {
+        let registry = default_agent_registry();
+        save_agent_registry_inner(&registry)?;
+        return Ok(registry);
+    }
+
+    let raw = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let mut registry = serde_json::from_str::<AgentRegistry>(&raw)
+        .map_err(|e| format!("Invalid agent registry {}: {e}", path.display()))?;
+    validate_agent_registry(&registry)?;
+    if upgrade_agent_registry(&mut registry) {
+        save_agent_registry_inner(&registry)?;
+    }
+    Ok(registry)
+}
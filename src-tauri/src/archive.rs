@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::Path;
+
+use crate::backup::{backup_info, create_backup_dir, load_backup_manifest, write_backup_manifest};
+use crate::blobs::{
+    open_sealed_blob, read_sealed_blob, read_verified_entry, seal_blobs, store_blob,
+    store_sealed_blob, StoredBlob,
+};
+use crate::encryption::{unlock_key, BackupKey};
+use crate::files::{content_hash, normalize_relative_path, write_atomic_bytes};
+use crate::journal::with_apply_lock;
+use crate::paths::resolve_scope_base;
+use crate::settings::{load_settings, EncryptionSettings};
+use crate::types::{BackupInfo, BackupManifest};
+use crate::workspace::ensure_workspace_layout;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const CHECKSUMS_NAME: &str = "checksums.sha256";
+const ENCRYPTION_NAME: &str = "encryption.json";
+const BLOBS_PREFIX: &str = "blobs/";
+pub const ARCHIVE_PASSPHRASE_REQUIRED: &str =
+    "The archive is encrypted; enter the passphrase it was exported with";
+
+fn append_file(
+    builder: &mut tar::Builder<Vec<u8>>,
+    name: &str,
+    data: &[u8],
+    mtime: u64,
+) -> Result<(), String> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, name, data)
+        .map_err(|e| e.to_string())
+}
+
+/// Writes `backup_id` to `destination` as a tar archive holding its manifest,
+/// the backed-up contents under `blobs/` and a `checksums.sha256` of all of
+/// them. Encrypted backups stay sealed: their blobs are copied as they are,
+/// with the passphrase-wrapped key in `encryption.json`.
+pub fn export_backup_inner(backup_id: &str, destination: &Path) -> Result<String, String> {
+    ensure_workspace_layout()?;
+    let encryption = load_settings()?.backup_encryption;
+    if encryption.is_some() {
+        // Finishes sealing blobs an interrupted switch to encryption left.
+        seal_blobs()?;
+    }
+    let (backup_dir, mut manifest) =
+        load_backup_manifest(backup_id).map_err(|_| format!("Backup not found: {backup_id}"))?;
+
+    let mut blobs = BTreeMap::new();
+    for entry in manifest
+        .entries
+        .iter_mut()
+        .filter(|entry| entry.existed_before)
+    {
+        let archived = match (&encryption, &entry.blob) {
+            (Some(_), Some(name)) => read_sealed_blob(name).map(|data| (name.clone(), data)),
+            (Some(_), None) => Err("Backup copy was never sealed".to_string()),
+            (None, _) => read_verified_entry(&backup_dir, entry, None)
+                .map(|content| (content_hash(&content), content)),
+        };
+        let (name, data) =
+            archived.map_err(|e| format!("{}/{}: {e}", entry.agent, entry.target_relative_path))?;
+        entry.blob = Some(name.clone());
+        blobs.insert(name, data);
+    }
+    manifest.restored_from = None;
+    let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    let encryption_json = encryption
+        .map(|encryption| serde_json::to_vec_pretty(&encryption))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    let mut checksums = format!("{}  {MANIFEST_NAME}\n", content_hash(&manifest_json));
+    if let Some(encryption_json) = &encryption_json {
+        checksums.push_str(&format!(
+            "{}  {ENCRYPTION_NAME}\n",
+            content_hash(encryption_json)
+        ));
+    }
+    for hash in blobs.keys() {
+        checksums.push_str(&format!("{hash}  {BLOBS_PREFIX}{hash}\n"));
+    }
+
+    let mtime = (manifest.created_at / 1000) as u64;
+    let mut builder = tar::Builder::new(Vec::new());
+    append_file(&mut builder, MANIFEST_NAME, &manifest_json, mtime)?;
+    if let Some(encryption_json) = &encryption_json {
+        append_file(&mut builder, ENCRYPTION_NAME, encryption_json, mtime)?;
+    }
+    for (hash, content) in &blobs {
+        append_file(
+            &mut builder,
+            &format!("{BLOBS_PREFIX}{hash}"),
+            content,
+            mtime,
+        )?;
+    }
+    append_file(&mut builder, CHECKSUMS_NAME, checksums.as_bytes(), mtime)?;
+    let archive = builder.into_inner().map_err(|e| e.to_string())?;
+    write_atomic_bytes(destination, &archive)?;
+    Ok(destination.display().to_string())
+}
+
+fn read_archive(path: &Path) -> Result<HashMap<String, Vec<u8>>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut archive = tar::Archive::new(file);
+    let mut files = HashMap::new();
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        files.insert(name, data);
+    }
+    Ok(files)
+}
+
+/// Checks every file listed in `checksums.sha256`. The manifest and the key
+/// of an encrypted archive must be listed; blobs are named by their hash and
+/// are checked against it.
+fn verify_archive(files: &HashMap<String, Vec<u8>>) -> Result<(), String> {
+    let checksums = files
+        .get(CHECKSUMS_NAME)
+        .ok_or_else(|| format!("Archive has no {CHECKSUMS_NAME}"))?;
+    let checksums = String::from_utf8_lossy(checksums);
+    let mut manifest_listed = false;
+    let mut encryption_listed = false;
+    for line in checksums.lines().filter(|line| !line.trim().is_empty()) {
+        let (hash, name) = line
+            .split_once("  ")
+            .ok_or_else(|| format!("Invalid checksum line: {line}"))?;
+        let data = files
+            .get(name)
+            .ok_or_else(|| format!("Archive is missing {name}"))?;
+        if content_hash(data) != hash {
+            return Err(format!("Checksum mismatch for {name}"));
+        }
+        manifest_listed |= name == MANIFEST_NAME;
+        encryption_listed |= name == ENCRYPTION_NAME;
+    }
+    if !manifest_listed {
+        return Err(format!("{MANIFEST_NAME} has no checksum"));
+    }
+    if files.contains_key(ENCRYPTION_NAME) && !encryption_listed {
+        return Err(format!("{ENCRYPTION_NAME} has no checksum"));
+    }
+    for (name, data) in files {
+        if let Some(hash) = name.strip_prefix(BLOBS_PREFIX) {
+            if content_hash(data) != hash {
+                return Err(format!("Checksum mismatch for {name}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stores the blob `data` of an archive sealed to `archived`. Blobs sealed to
+/// this store's key are kept as they are; others are opened with the
+/// archive's `passphrase` and stored like any new content.
+fn import_sealed_blob(
+    data: &[u8],
+    archived: &EncryptionSettings,
+    local: Option<&EncryptionSettings>,
+    passphrase: Option<&str>,
+    key: &mut Option<BackupKey>,
+) -> Result<StoredBlob, String> {
+    if local.is_some_and(|local| local.public_key == archived.public_key) {
+        return Ok(StoredBlob {
+            name: store_sealed_blob(data)?,
+            sha256: None,
+        });
+    }
+    if key.is_none() {
+        let passphrase = passphrase
+            .filter(|passphrase| !passphrase.is_empty())
+            .ok_or_else(|| ARCHIVE_PASSPHRASE_REQUIRED.to_string())?;
+        *key = Some(unlock_key(archived, passphrase)?);
+    }
+    let content = key
+        .as_ref()
+        .and_then(|key| open_sealed_blob(data, key))
+        .ok_or_else(|| "Archive content does not open with its key".to_string())?;
+    store_blob(&content)
+}
+
+/// Imports an archive written by `export_backup_inner` as a new backup.
+/// Targets are resolved from each entry's agent and relative path against
+/// this machine's roots; the archived absolute paths are ignored. An
+/// encrypted archive needs its `passphrase` unless it was sealed to this
+/// store's key.
+pub fn import_backup_inner(archive: &Path, passphrase: Option<&str>) -> Result<BackupInfo, String> {
+    ensure_workspace_layout()?;
+    let files = read_archive(archive)?;
+    verify_archive(&files)?;
+    let manifest_json = &files[MANIFEST_NAME];
+    let mut manifest = serde_json::from_slice::<BackupManifest>(manifest_json)
+        .map_err(|e| format!("Invalid backup manifest: {e}"))?;
+
+    for entry in &mut manifest.entries {
+        let relative = normalize_relative_path(&entry.target_relative_path)
+            .map_err(|e| format!("{}: {e}", entry.target_relative_path))?;
+        entry.target_absolute_path = resolve_scope_base(&entry.agent)?
+            .join(relative)
+            .display()
+            .to_string();
+        if let Some(hash) = &entry.blob {
+            if !files.contains_key(&format!("{BLOBS_PREFIX}{hash}")) {
+                return Err(format!(
+                    "Archive is missing the content of {}/{}",
+                    entry.agent, entry.target_relative_path
+                ));
+            }
+        }
+    }
+
+    let archived = files
+        .get(ENCRYPTION_NAME)
+        .map(|raw| serde_json::from_slice::<EncryptionSettings>(raw))
+        .transpose()
+        .map_err(|e| format!("Invalid {ENCRYPTION_NAME}: {e}"))?;
+    // Under the lock, so garbage collection cannot drop the blobs stored
+    // before the manifest that references them is written.
+    with_apply_lock(|| {
+        let local = load_settings()?.backup_encryption;
+        let mut key = None;
+        let mut stored = HashMap::new();
+        for (name, data) in &files {
+            if let Some(hash) = name.strip_prefix(BLOBS_PREFIX) {
+                let blob = match &archived {
+                    Some(archived) => {
+                        import_sealed_blob(data, archived, local.as_ref(), passphrase, &mut key)?
+                    }
+                    None => store_blob(data)?,
+                };
+                stored.insert(hash, blob);
+            }
+        }
+        for entry in &mut manifest.entries {
+            if let Some(blob) = entry.blob.as_deref().and_then(|hash| stored.get(hash)) {
+                entry.blob = Some(blob.name.clone());
+                entry.sha256 = blob.sha256.clone();
+            }
+        }
+        let (backup_id, backup_dir) = create_backup_dir()?;
+        manifest.backup_id = backup_id;
+        manifest.pinned = false;
+        manifest.restored_from = None;
+        write_backup_manifest(&backup_dir, &manifest)
+    })?;
+
+    backup_info(&manifest)
+}
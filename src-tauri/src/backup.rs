@@ -1,15 +1,83 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::files::{read_text, write_atomic_bytes};
+use crate::agents::load_agent_registry;
+use crate::blobs::{
+    collect_garbage, inspect_backup_entry, read_backup_entry, read_blob, store_blob,
+    stored_blob_size, ENTRY_MISSING,
+};
+use crate::encryption::{unlock_backup_key, BackupKey};
+use crate::files::{now_millis, read_text, write_atomic_bytes};
+use crate::journal::{with_apply_lock, ApplyTransaction, JournalTarget};
+use crate::mapping::{load_mapping, MappingConfig};
 use crate::paths::{backups_root, source_root};
 use crate::types::{
-    BackupDetail, BackupDetailEntry, BackupEntry, BackupInfo, BackupManifest, RestoreResult,
-    RestoredSourcePrompts,
+    ApplySyncResult, BackupDetail, BackupDetailEntry, BackupEntry, BackupInfo, BackupManifest,
+    RestoreFilter, RestorePlanEntry, RestorePreview, RestoreResult, RestoredSourcePrompts,
 };
 use crate::workspace::ensure_workspace_layout;
 
-fn load_backup_manifest(backup_id: &str) -> Result<(PathBuf, BackupManifest), String> {
+const RESTORE_TRIGGER: &str = "restore";
+
+/// Allocates a new timestamped backup directory. Backups taken within the
+/// same millisecond get the next free timestamp.
+pub fn create_backup_dir() -> Result<(String, PathBuf), String> {
+    let root = backups_root()?;
+    fs::create_dir_all(&root).map_err(|e| e.to_string())?;
+    let mut stamp = now_millis()?;
+    loop {
+        let backup_id = stamp.to_string();
+        let backup_dir = root.join(&backup_id);
+        match fs::create_dir(&backup_dir) {
+            Ok(()) => return Ok((backup_id, backup_dir)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => stamp += 1,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Stores `content` (`None` when the file did not exist) in the blob store
+/// and returns its manifest entry.
+pub fn backup_entry(
+    agent: &str,
+    relative: &str,
+    absolute: &Path,
+    content: Option<&[u8]>,
+) -> Result<BackupEntry, String> {
+    let stored = content.map(store_blob).transpose()?;
+    Ok(BackupEntry {
+        agent: agent.to_string(),
+        target_relative_path: relative.to_string(),
+        target_absolute_path: absolute.display().to_string(),
+        existed_before: content.is_some(),
+        size: content.map(|content| content.len() as u64),
+        sha256: stored.as_ref().and_then(|stored| stored.sha256.clone()),
+        blob: stored.map(|stored| stored.name),
+    })
+}
+
+/// Stores the current file (if any) in the blob store and returns its
+/// manifest entry.
+pub fn backup_existing_file(
+    agent: &str,
+    relative: &str,
+    absolute: &Path,
+) -> Result<BackupEntry, String> {
+    let original = if absolute.exists() {
+        Some(fs::read(absolute).map_err(|e| e.to_string())?)
+    } else {
+        None
+    };
+    backup_entry(agent, relative, absolute, original.as_deref())
+}
+
+pub fn write_backup_manifest(backup_dir: &Path, manifest: &BackupManifest) -> Result<(), String> {
+    let payload = serde_json::to_string_pretty(manifest).map_err(|e| e.to_string())?;
+    write_atomic_bytes(&backup_dir.join("manifest.json"), payload.as_bytes())
+}
+
+pub fn load_backup_manifest(backup_id: &str) -> Result<(PathBuf, BackupManifest), String> {
     let backup_dir = backups_root()?.join(backup_id);
     let manifest_path = backup_dir.join("manifest.json");
     let raw = fs::read_to_string(&manifest_path).map_err(|e| e.to_string())?;
@@ -18,14 +86,83 @@ fn load_backup_manifest(backup_id: &str) -> Result<(PathBuf, BackupManifest), St
     Ok((backup_dir, manifest))
 }
 
-pub fn list_backups_inner() -> Result<Vec<BackupInfo>, String> {
+/// Manifest format written by this version.
+const MANIFEST_VERSION: u32 = 1;
+
+impl BackupManifest {
+    pub fn new(
+        backup_id: String,
+        created_at: u128,
+        trigger: &str,
+        entries: Vec<BackupEntry>,
+    ) -> Self {
+        BackupManifest {
+            version: MANIFEST_VERSION,
+            app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            backup_id,
+            created_at,
+            trigger: trigger.to_string(),
+            entries,
+            pinned: false,
+            restored_from: None,
+            label: None,
+            note: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// `(total_bytes, stored_bytes)` of a backup's entries; each blob is
+/// counted once.
+fn backup_sizes(manifest: &BackupManifest) -> Result<(u64, u64), String> {
+    let backup_dir = backups_root()?.join(&manifest.backup_id);
+    let mut seen = HashSet::new();
+    let (mut total, mut stored) = (0, 0);
+    for entry in manifest.entries.iter().filter(|entry| entry.existed_before) {
+        let Some(hash) = &entry.blob else {
+            let copy_size = read_backup_entry(&backup_dir, entry, None)?
+                .map_or(0, |content| content.len() as u64);
+            total += copy_size;
+            stored += copy_size;
+            continue;
+        };
+        total += match entry.size {
+            Some(size) => size,
+            None => read_blob(hash, None).map_or(0, |content| content.len() as u64),
+        };
+        if seen.insert(hash) {
+            stored += stored_blob_size(hash)?.unwrap_or(0);
+        }
+    }
+    Ok((total, stored))
+}
+
+pub fn backup_info(manifest: &BackupManifest) -> Result<BackupInfo, String> {
+    let (total_bytes, stored_bytes) = backup_sizes(manifest)?;
+    Ok(BackupInfo {
+        backup_id: manifest.backup_id.clone(),
+        created_at: manifest.created_at,
+        trigger: manifest.trigger.clone(),
+        entry_count: manifest.entries.len(),
+        pinned: manifest.pinned,
+        restored_from: manifest.restored_from.clone(),
+        label: manifest.label.clone(),
+        note: manifest.note.clone(),
+        tags: manifest.tags.clone(),
+        total_bytes,
+        stored_bytes,
+    })
+}
+
+/// Every backup manifest, newest first.
+pub fn list_backup_manifests() -> Result<Vec<BackupManifest>, String> {
     ensure_workspace_layout()?;
     let root = backups_root()?;
     if !root.exists() {
         return Ok(Vec::new());
     }
 
-    let mut items = Vec::new();
+    let mut manifests = Vec::new();
     for entry in fs::read_dir(root).map_err(|e| e.to_string())? {
         let entry = entry.map_err(|e| e.to_string())?;
         let backup_dir = entry.path();
@@ -41,17 +178,15 @@ pub fn list_backups_inner() -> Result<Vec<BackupInfo>, String> {
         let raw = fs::read_to_string(&manifest_path).map_err(|e| e.to_string())?;
         let manifest =
             serde_json::from_str::<BackupManifest>(&raw).map_err(|e| e.to_string())?;
-
-        items.push(BackupInfo {
-            backup_id: manifest.backup_id,
-            created_at: manifest.created_at,
-            trigger: manifest.trigger,
-            entry_count: manifest.entries.len(),
-        });
+        manifests.push(manifest);
     }
 
-    items.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-    Ok(items)
+    manifests.sort_by_key(|manifest| std::cmp::Reverse(manifest.created_at));
+    Ok(manifests)
+}
+
+pub fn list_backups_inner() -> Result<Vec<BackupInfo>, String> {
+    list_backup_manifests()?.iter().map(backup_info).collect()
 }
 
 #[derive(Clone)]
@@ -61,23 +196,91 @@ enum RestoreAction {
     None,
 }
 
-pub fn restore_backup_inner(backup_id: String) -> Result<RestoreResult, String> {
-    ensure_workspace_layout()?;
-    let (backup_dir, manifest) = load_backup_manifest(&backup_id)?;
+impl RestoreAction {
+    fn name(&self) -> &'static str {
+        match self {
+            RestoreAction::Write(_) => "write",
+            RestoreAction::Delete => "delete",
+            RestoreAction::None => "none",
+        }
+    }
+}
 
-    let mut planned: Vec<(BackupEntry, RestoreAction)> = Vec::new();
-    for entry in &manifest.entries {
-        let target = PathBuf::from(&entry.target_absolute_path);
-        if entry.existed_before {
-            let backup_file = backup_dir
-                .join(&entry.agent)
-                .join(Path::new(&entry.target_relative_path));
-            if !backup_file.exists() {
-                continue;
-            }
+struct PlannedRestore {
+    entry: BackupEntry,
+    category: Option<String>,
+    action: RestoreAction,
+}
 
-            let desired = fs::read(&backup_file).map_err(|e| e.to_string())?;
-            let action = if target.exists() {
+/// Whether `path` is `prefix` or lies under it.
+fn path_within(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The first folder of `source` entries; for agent entries, the category
+/// whose current target contains the path.
+fn entry_category(mapping: &MappingConfig, entry: &BackupEntry) -> Option<String> {
+    let relative = entry.target_relative_path.as_str();
+    if entry.agent == "source" {
+        return relative.split('/').next().map(str::to_string);
+    }
+    mapping
+        .categories
+        .iter()
+        .find(|(_, category)| {
+            category
+                .target_for(&entry.agent)
+                .is_some_and(|target| path_within(relative, target))
+        })
+        .map(|(name, _)| name.clone())
+}
+
+fn matches_filter(filter: &RestoreFilter, entry: &BackupEntry, category: Option<&str>) -> bool {
+    (filter.agents.is_empty() || filter.agents.contains(&entry.agent))
+        && (filter.paths.is_empty()
+            || filter
+                .paths
+                .iter()
+                .any(|path| path_within(&entry.target_relative_path, path)))
+        && (filter.categories.is_empty()
+            || category
+                .is_some_and(|category| filter.categories.iter().any(|wanted| wanted == category)))
+}
+
+fn plan_restore(
+    backup_id: &str,
+    filter: &RestoreFilter,
+    key: Option<&BackupKey>,
+) -> Result<Vec<PlannedRestore>, String> {
+    let (backup_dir, manifest) = load_backup_manifest(backup_id)?;
+    let mapping = load_mapping()?;
+
+    let mut planned = Vec::new();
+    let mut corrupted = Vec::new();
+    for entry in manifest.entries {
+        let category = entry_category(&mapping, &entry);
+        if !matches_filter(filter, &entry, category.as_deref()) {
+            continue;
+        }
+        let target = PathBuf::from(&entry.target_absolute_path);
+        let action = if entry.existed_before {
+            let desired = match inspect_backup_entry(&backup_dir, &entry, key)? {
+                (Some(desired), None) => desired,
+                (_, problem) => {
+                    corrupted.push(format!(
+                        "{}/{} ({})",
+                        entry.agent,
+                        entry.target_relative_path,
+                        problem.unwrap_or(ENTRY_MISSING)
+                    ));
+                    continue;
+                }
+            };
+            if target.exists() {
                 let current = fs::read(&target).map_err(|e| e.to_string())?;
                 if current == desired {
                     RestoreAction::None
@@ -86,55 +289,130 @@ pub fn restore_backup_inner(backup_id: String) -> Result<RestoreResult, String>
                 }
             } else {
                 RestoreAction::Write(desired)
-            };
-
-            planned.push((entry.clone(), action));
+            }
+        } else if target.exists() {
+            RestoreAction::Delete
         } else {
-            let action = if target.exists() {
-                RestoreAction::Delete
-            } else {
-                RestoreAction::None
-            };
-            planned.push((entry.clone(), action));
-        }
+            RestoreAction::None
+        };
+        planned.push(PlannedRestore {
+            entry,
+            category,
+            action,
+        });
     }
 
-    let changed: Vec<(BackupEntry, RestoreAction)> = planned
+    if !corrupted.is_empty() {
+        return Err(format!(
+            "Backup {backup_id} is damaged, nothing was restored: {}",
+            corrupted.join(", ")
+        ));
+    }
+    if planned.is_empty() && !filter.is_empty() {
+        return Err(format!("No entries of backup {backup_id} match the filter"));
+    }
+    Ok(planned)
+}
+
+/// What restoring `backup_id` (limited to `filter`) would do, without doing it.
+pub fn preview_restore_inner(
+    backup_id: String,
+    filter: &RestoreFilter,
+    passphrase: Option<&str>,
+) -> Result<RestorePreview, String> {
+    ensure_workspace_layout()?;
+    let key = unlock_backup_key(passphrase)?;
+    let entries = plan_restore(&backup_id, filter, key.as_ref())?
         .into_iter()
-        .filter(|(_, action)| !matches!(action, RestoreAction::None))
+        .map(|planned| RestorePlanEntry {
+            agent: planned.entry.agent,
+            target_relative_path: planned.entry.target_relative_path,
+            target_absolute_path: planned.entry.target_absolute_path,
+            category: planned.category,
+            action: planned.action.name().to_string(),
+        })
         .collect();
+    Ok(RestorePreview { backup_id, entries })
+}
 
-    let mut restored = 0;
-    for (entry, action) in changed {
-        let target = PathBuf::from(&entry.target_absolute_path);
-        match action {
-            RestoreAction::Write(content) => {
-                write_atomic_bytes(&target, &content)?;
-                restored += 1;
-            }
-            RestoreAction::Delete => {
-                if target.exists() {
-                    fs::remove_file(&target).map_err(|e| e.to_string())?;
-                    restored += 1;
-                }
-            }
-            RestoreAction::None => {}
+/// Why an apply failed, including files its rollback could not put back.
+pub fn apply_failure_message(result: ApplySyncResult) -> String {
+    let Some(failure) = result.failure else {
+        return "Apply failed".to_string();
+    };
+    let mut message = match &failure.path {
+        Some(path) => format!("{path}: {}", failure.error),
+        None => failure.error,
+    };
+    if !failure.rollback_errors.is_empty() {
+        message.push_str(&format!(
+            "; not rolled back: {}",
+            failure.rollback_errors.join(", ")
+        ));
+    }
+    message
+}
+
+/// Restores the backup, limited to `filter`. The files it changes are backed
+/// up first (trigger `restore`), so the restore itself can be undone.
+pub fn restore_backup_inner(
+    backup_id: String,
+    filter: &RestoreFilter,
+    passphrase: Option<&str>,
+) -> Result<RestoreResult, String> {
+    ensure_workspace_layout()?;
+    let key = unlock_backup_key(passphrase)?;
+    let changed: Vec<PlannedRestore> = plan_restore(&backup_id, filter, key.as_ref())?
+        .into_iter()
+        .filter(|planned| !matches!(planned.action, RestoreAction::None))
+        .collect();
+
+    let restored_count = changed.len();
+    let mut safety_backup_id = None;
+    if !changed.is_empty() {
+        let targets: Vec<JournalTarget> = changed
+            .iter()
+            .map(|planned| JournalTarget {
+                agent: planned.entry.agent.clone(),
+                relative: planned.entry.target_relative_path.clone(),
+                absolute: PathBuf::from(&planned.entry.target_absolute_path),
+            })
+            .collect();
+        let mut transaction =
+            ApplyTransaction::begin(RESTORE_TRIGGER, &targets)?.restoring(&backup_id);
+        let outcome = changed.into_iter().try_for_each(|planned| {
+            let target = PathBuf::from(&planned.entry.target_absolute_path);
+            transaction.step(|| match planned.action {
+                RestoreAction::Write(content) => write_atomic_bytes(&target, &content),
+                RestoreAction::Delete => fs::remove_file(&target).map_err(|e| e.to_string()),
+                RestoreAction::None => Ok(()),
+            })
+        });
+        if let Err(error) = outcome {
+            return Err(apply_failure_message(transaction.rollback(error)));
         }
+        safety_backup_id = Some(transaction.commit(Vec::new())?);
     }
+
     let source_prompts = load_source_prompts()?;
     Ok(RestoreResult {
-        restored_count: restored,
+        restored_count,
+        backup_id: safety_backup_id,
         source_prompts,
     })
 }
 
 fn load_source_prompts() -> Result<RestoredSourcePrompts, String> {
     let root = source_root()?.join("instructions");
+    let registry = load_agent_registry()?;
+    let mut agents = BTreeMap::new();
+    for agent in &registry.agents {
+        let content = read_prompt_file_or_empty(&root.join(format!("{}.md", agent.id)))?;
+        agents.insert(agent.id.clone(), content);
+    }
     Ok(RestoredSourcePrompts {
         base: read_prompt_file_or_empty(&root.join("base.md"))?,
-        codex: read_prompt_file_or_empty(&root.join("codex.md"))?,
-        gemini: read_prompt_file_or_empty(&root.join("gemini.md"))?,
-        claude: read_prompt_file_or_empty(&root.join("claude.md"))?,
+        agents,
     })
 }
 
@@ -147,29 +425,32 @@ fn read_prompt_file_or_empty(path: &Path) -> Result<String, String> {
 
 pub fn delete_backup_inner(backup_id: String) -> Result<(), String> {
     ensure_workspace_layout()?;
-    let backup_dir = backups_root()?.join(&backup_id);
-    if !backup_dir.exists() {
-        return Err(format!("Backup not found: {backup_id}"));
-    }
-    fs::remove_dir_all(&backup_dir).map_err(|e| e.to_string())?;
-    Ok(())
+    let (backup_dir, _) =
+        load_backup_manifest(&backup_id).map_err(|_| format!("Backup not found: {backup_id}"))?;
+    with_apply_lock(|| {
+        fs::remove_dir_all(&backup_dir).map_err(|e| e.to_string())?;
+        collect_garbage()?;
+        Ok(())
+    })
 }
 
-pub fn get_backup_detail_inner(backup_id: String) -> Result<BackupDetail, String> {
+pub fn get_backup_detail_inner(
+    backup_id: String,
+    passphrase: Option<&str>,
+) -> Result<BackupDetail, String> {
     ensure_workspace_layout()?;
+    let key = unlock_backup_key(passphrase)?;
     let (backup_dir, manifest) = load_backup_manifest(&backup_id)?;
 
     let mut entries = Vec::new();
     for entry in &manifest.entries {
-        let backup_file = backup_dir
-            .join(&entry.agent)
-            .join(std::path::Path::new(&entry.target_relative_path));
-
-        let backup_content = if entry.existed_before && backup_file.exists() {
-            Some(read_text(&backup_file)?)
-        } else {
-            None
+        let inspected = inspect_backup_entry(&backup_dir, entry, key.as_ref());
+        let (backup_content, problem) = match inspected {
+            Ok((content, problem)) => (content, problem.map(str::to_string)),
+            Err(error) => (None, Some(error)),
         };
+        let backup_content =
+            backup_content.map(|content| String::from_utf8_lossy(&content).into_owned());
 
         let current_target = PathBuf::from(&entry.target_absolute_path);
         let current_content = if current_target.exists() {
@@ -184,6 +465,7 @@ pub fn get_backup_detail_inner(backup_id: String) -> Result<BackupDetail, String
             existed_before: entry.existed_before,
             backup_content,
             current_content,
+            problem,
         });
     }
 
@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    agentdock_lib::run_cli()
+}
@@ -0,0 +1,562 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use x25519_dalek::PublicKey;
+
+use crate::encryption::{backup_public_key, open, seal, BackupKey, PASSPHRASE_REQUIRED};
+use crate::files::{content_hash, write_atomic_bytes};
+use crate::paths::{backups_root, objects_root};
+use crate::settings::{load_settings, BackupCompression};
+use crate::types::BackupEntry;
+
+const MANIFEST_LIST: &str = "manifest.json";
+const JOURNAL_LIST: &str = "journal.json";
+const GZIP_SUFFIX: &str = ".gz";
+const SEALED_SUFFIX: &str = ".enc";
+
+pub const ENTRY_MISSING: &str = "missing";
+const ENTRY_TRUNCATED: &str = "truncated";
+const ENTRY_MODIFIED: &str = "modified";
+
+#[derive(Deserialize)]
+struct EntryList {
+    entries: Vec<BackupEntry>,
+}
+
+/// Where `store_blob` put some content.
+pub struct StoredBlob {
+    pub name: String,
+    /// SHA-256 of the content; `None` for sealed blobs, which are named after
+    /// their ciphertext so that nothing stored reveals what they contain.
+    pub sha256: Option<String>,
+}
+
+/// How a blob file is written. Sealed blobs hold a compression tag byte and
+/// the stored bytes, encrypted to the backup key.
+#[derive(Clone, Copy)]
+enum BlobFormat {
+    Stored(BackupCompression),
+    Sealed,
+}
+
+fn blob_path(hash: &str) -> Result<PathBuf, String> {
+    if hash.len() < 3 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(format!("Invalid blob hash: {hash}"));
+    }
+    Ok(objects_root()?.join(&hash[..2]).join(&hash[2..]))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn format_path(hash: &str, format: BlobFormat) -> Result<PathBuf, String> {
+    let path = blob_path(hash)?;
+    Ok(match format {
+        BlobFormat::Stored(BackupCompression::None) => path,
+        BlobFormat::Stored(BackupCompression::Gzip) => with_suffix(&path, GZIP_SUFFIX),
+        BlobFormat::Sealed => with_suffix(&path, SEALED_SUFFIX),
+    })
+}
+
+/// Where a blob is kept and how. A readable copy wins over a sealed one.
+fn find_blob(hash: &str) -> Result<Option<(PathBuf, BlobFormat)>, String> {
+    let formats = [
+        BlobFormat::Stored(BackupCompression::None),
+        BlobFormat::Stored(BackupCompression::Gzip),
+        BlobFormat::Sealed,
+    ];
+    for format in formats {
+        let path = format_path(hash, format)?;
+        if path.is_file() {
+            return Ok(Some((path, format)));
+        }
+    }
+    Ok(None)
+}
+
+fn compression_tag(compression: BackupCompression) -> u8 {
+    match compression {
+        BackupCompression::None => 0,
+        BackupCompression::Gzip => 1,
+    }
+}
+
+fn tag_compression(tag: u8) -> Option<BackupCompression> {
+    match tag {
+        0 => Some(BackupCompression::None),
+        1 => Some(BackupCompression::Gzip),
+        _ => None,
+    }
+}
+
+fn encode(content: &[u8], compression: BackupCompression) -> Result<Vec<u8>, String> {
+    match compression {
+        BackupCompression::None => Ok(content.to_vec()),
+        BackupCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(content).map_err(|e| e.to_string())?;
+            encoder.finish().map_err(|e| e.to_string())
+        }
+    }
+}
+
+fn decode(stored: Vec<u8>, compression: BackupCompression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        BackupCompression::None => Ok(stored),
+        BackupCompression::Gzip => {
+            let mut content = Vec::new();
+            GzDecoder::new(stored.as_slice()).read_to_end(&mut content)?;
+            Ok(content)
+        }
+    }
+}
+
+/// Seals stored bytes to `recipient`. Returns the blob's name (the hash of
+/// the ciphertext) and its sealed bytes.
+fn seal_stored(
+    stored: Vec<u8>,
+    compression: BackupCompression,
+    recipient: &PublicKey,
+) -> Result<(String, Vec<u8>), String> {
+    let mut plain = vec![compression_tag(compression)];
+    plain.extend(stored);
+    let sealed = seal(&plain, recipient)?;
+    Ok((content_hash(&sealed), sealed))
+}
+
+/// The compression and stored bytes inside a sealed blob.
+fn open_sealed(sealed: &[u8], key: &BackupKey) -> Option<(BackupCompression, Vec<u8>)> {
+    let mut plain = open(sealed, key)?;
+    let compression = tag_compression(*plain.first()?)?;
+    plain.remove(0);
+    Some((compression, plain))
+}
+
+/// Reads and decodes a blob file. The inner error says why its content
+/// cannot be trusted; sealed blobs are checked against their `name` and
+/// need `key`.
+fn load_blob(
+    path: &Path,
+    format: BlobFormat,
+    name: Option<&str>,
+    key: Option<&BackupKey>,
+) -> Result<Result<Vec<u8>, &'static str>, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let (compression, stored) = match format {
+        BlobFormat::Stored(compression) => (compression, data),
+        BlobFormat::Sealed => {
+            if name.is_some_and(|name| content_hash(&data) != name) {
+                return Ok(Err(ENTRY_MODIFIED));
+            }
+            let key = key.ok_or_else(|| PASSPHRASE_REQUIRED.to_string())?;
+            match open_sealed(&data, key) {
+                Some(opened) => opened,
+                None => return Ok(Err(ENTRY_MODIFIED)),
+            }
+        }
+    };
+    Ok(decode(stored, compression).map_err(|e| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            ENTRY_TRUNCATED
+        } else {
+            ENTRY_MODIFIED
+        }
+    }))
+}
+
+/// Stores `content`, compressed as configured in the settings. While
+/// backups are encrypted it is sealed right away; otherwise it is stored
+/// once under its hash.
+pub fn store_blob(content: &[u8]) -> Result<StoredBlob, String> {
+    let compression = load_settings()?.backup_compression;
+    if let Some(recipient) = backup_public_key()? {
+        let (name, sealed) = seal_stored(encode(content, compression)?, compression, &recipient)?;
+        write_atomic_bytes(&format_path(&name, BlobFormat::Sealed)?, &sealed)?;
+        return Ok(StoredBlob { name, sha256: None });
+    }
+    let hash = content_hash(content);
+    if find_blob(&hash)?.is_none() {
+        write_atomic_bytes(
+            &format_path(&hash, BlobFormat::Stored(compression))?,
+            &encode(content, compression)?,
+        )?;
+    }
+    Ok(StoredBlob {
+        name: hash.clone(),
+        sha256: Some(hash),
+    })
+}
+
+pub fn read_blob(hash: &str, key: Option<&BackupKey>) -> Result<Vec<u8>, String> {
+    let (path, format) = find_blob(hash)?.ok_or_else(|| format!("Missing backup blob {hash}"))?;
+    load_blob(&path, format, Some(hash), key)?
+        .map_err(|problem| format!("Backup blob {hash} is {problem}"))
+}
+
+/// The bytes of sealed blob `name`, checked against its name.
+pub fn read_sealed_blob(name: &str) -> Result<Vec<u8>, String> {
+    let path = match find_blob(name)? {
+        Some((path, BlobFormat::Sealed)) => path,
+        Some(_) => return Err(format!("Backup blob {name} is not sealed")),
+        None => return Err(format!("Missing backup blob {name}")),
+    };
+    let data = fs::read(&path).map_err(|e| e.to_string())?;
+    if content_hash(&data) != name {
+        return Err(format!("Backup blob {name} is {ENTRY_MODIFIED}"));
+    }
+    Ok(data)
+}
+
+/// Adds a blob sealed by another store to the same key as this one, under
+/// the hash of its bytes. Returns that name.
+pub fn store_sealed_blob(data: &[u8]) -> Result<String, String> {
+    let name = content_hash(data);
+    let path = format_path(&name, BlobFormat::Sealed)?;
+    if !path.exists() {
+        write_atomic_bytes(&path, data)?;
+    }
+    Ok(name)
+}
+
+/// The content of a sealed blob, or `None` when it does not open with `key`.
+pub fn open_sealed_blob(data: &[u8], key: &BackupKey) -> Option<Vec<u8>> {
+    let (compression, stored) = open_sealed(data, key)?;
+    decode(stored, compression).ok()
+}
+
+/// Bytes the blob takes on disk, `None` when it is missing.
+pub fn stored_blob_size(hash: &str) -> Result<Option<u64>, String> {
+    match find_blob(hash)? {
+        Some((path, _)) => fs::metadata(&path)
+            .map(|metadata| Some(metadata.len()))
+            .map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+fn legacy_copy_path(backup_dir: &Path, entry: &BackupEntry) -> PathBuf {
+    backup_dir
+        .join(&entry.agent)
+        .join(Path::new(&entry.target_relative_path))
+}
+
+/// The backed-up content of `entry`: its blob, or the per-backup copy of
+/// backups made before the blob store. `None` when the file did not exist
+/// before or its copy is gone.
+pub fn read_backup_entry(
+    backup_dir: &Path,
+    entry: &BackupEntry,
+    key: Option<&BackupKey>,
+) -> Result<Option<Vec<u8>>, String> {
+    if !entry.existed_before {
+        return Ok(None);
+    }
+    if let Some(hash) = &entry.blob {
+        return read_blob(hash, key).map(Some);
+    }
+    let legacy = legacy_copy_path(backup_dir, entry);
+    if !legacy.exists() {
+        return Ok(None);
+    }
+    fs::read(&legacy).map(Some).map_err(|e| e.to_string())
+}
+
+/// The stored content of `entry` and, when it cannot be trusted, whether it
+/// is missing, truncated or modified. Content is checked against the
+/// recorded size and hash; blobs are always checked against their name,
+/// readable ones by their content and sealed ones by their ciphertext.
+pub fn inspect_backup_entry(
+    backup_dir: &Path,
+    entry: &BackupEntry,
+    key: Option<&BackupKey>,
+) -> Result<(Option<Vec<u8>>, Option<&'static str>), String> {
+    if !entry.existed_before {
+        return Ok((None, None));
+    }
+    let stored = match &entry.blob {
+        Some(hash) => find_blob(hash)?,
+        None => Some(legacy_copy_path(backup_dir, entry))
+            .filter(|path| path.is_file())
+            .map(|path| (path, BlobFormat::Stored(BackupCompression::None))),
+    };
+    let Some((path, format)) = stored else {
+        return Ok((None, Some(ENTRY_MISSING)));
+    };
+    let content = match load_blob(&path, format, entry.blob.as_deref(), key)? {
+        Ok(content) => content,
+        Err(problem) => return Ok((None, Some(problem))),
+    };
+    let short = entry.size.is_some_and(|size| (content.len() as u64) < size);
+    let expected_hash = match format {
+        BlobFormat::Stored(_) => entry.sha256.as_ref().or(entry.blob.as_ref()),
+        BlobFormat::Sealed => entry.sha256.as_ref(),
+    };
+    let problem = if short {
+        Some(ENTRY_TRUNCATED)
+    } else if expected_hash.is_some_and(|hash| content_hash(&content) != *hash)
+        || entry.size.is_some_and(|size| content.len() as u64 != size)
+    {
+        Some(ENTRY_MODIFIED)
+    } else {
+        None
+    };
+    Ok((Some(content), problem))
+}
+
+/// Like `read_backup_entry`, but fails when the stored content is damaged.
+pub fn read_verified_entry(
+    backup_dir: &Path,
+    entry: &BackupEntry,
+    key: Option<&BackupKey>,
+) -> Result<Vec<u8>, String> {
+    match inspect_backup_entry(backup_dir, entry, key)? {
+        (Some(content), None) => Ok(content),
+        (_, problem) => Err(format!(
+            "Backup copy is {}",
+            problem.unwrap_or(ENTRY_MISSING)
+        )),
+    }
+}
+
+fn backup_dirs() -> Result<Vec<PathBuf>, String> {
+    let root = backups_root()?;
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+    let objects = objects_root()?;
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(&root).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() && path != objects {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+/// Blobs referenced by the `lists` of any backup.
+fn referenced_blobs(lists: &[&str]) -> Result<HashSet<String>, String> {
+    let mut referenced = HashSet::new();
+    for backup_dir in backup_dirs()? {
+        for name in lists {
+            let path = backup_dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let raw = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            let list = serde_json::from_str::<EntryList>(&raw)
+                .map_err(|e| format!("Invalid backup {}: {e}", path.display()))?;
+            referenced.extend(list.entries.into_iter().filter_map(|entry| entry.blob));
+        }
+    }
+    Ok(referenced)
+}
+
+/// Every blob file with its hash and format. Leftover temporary files are
+/// skipped.
+fn blob_files() -> Result<Vec<(String, PathBuf, BlobFormat)>, String> {
+    let objects = objects_root()?;
+    if !objects.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for prefix in fs::read_dir(&objects).map_err(|e| e.to_string())? {
+        let prefix = prefix.map_err(|e| e.to_string())?;
+        let prefix_name = prefix.file_name().to_string_lossy().into_owned();
+        for blob in fs::read_dir(prefix.path()).map_err(|e| e.to_string())? {
+            let blob = blob.map_err(|e| e.to_string())?;
+            let file_name = blob.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with('.') {
+                continue;
+            }
+            let (rest, format) = if let Some(rest) = file_name.strip_suffix(SEALED_SUFFIX) {
+                (rest, BlobFormat::Sealed)
+            } else if let Some(rest) = file_name.strip_suffix(GZIP_SUFFIX) {
+                (rest, BlobFormat::Stored(BackupCompression::Gzip))
+            } else {
+                (
+                    file_name.as_str(),
+                    BlobFormat::Stored(BackupCompression::None),
+                )
+            };
+            files.push((format!("{prefix_name}{rest}"), blob.path(), format));
+        }
+    }
+    Ok(files)
+}
+
+/// Deletes blobs no backup references any more. Returns how many went.
+pub fn collect_garbage() -> Result<usize, String> {
+    let referenced = referenced_blobs(&[MANIFEST_LIST, JOURNAL_LIST])?;
+    let mut removed = 0;
+    for (hash, path, _) in blob_files()? {
+        if !referenced.contains(&hash) {
+            fs::remove_file(&path).map_err(|e| e.to_string())?;
+            removed += 1;
+        }
+    }
+    if let Ok(prefixes) = fs::read_dir(objects_root()?) {
+        for prefix in prefixes.flatten() {
+            let _ = fs::remove_dir(prefix.path());
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs `update` on the entries of every manifest and journal and rewrites
+/// the lists it changed. Returns the backup folders that have entry lists.
+fn update_entries(
+    mut update: impl FnMut(&Path, &mut BackupEntry) -> Result<bool, String>,
+) -> Result<Vec<PathBuf>, String> {
+    let mut listed = Vec::new();
+    for backup_dir in backup_dirs()? {
+        let mut has_entries = false;
+        for name in [MANIFEST_LIST, JOURNAL_LIST] {
+            let path = backup_dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let raw = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            let mut list = serde_json::from_str::<Value>(&raw)
+                .map_err(|e| format!("Invalid backup {}: {e}", path.display()))?;
+            let Some(entries) = list.get_mut("entries").and_then(Value::as_array_mut) else {
+                continue;
+            };
+            has_entries = true;
+            let mut changed = false;
+            for value in entries.iter_mut() {
+                let mut entry = serde_json::from_value::<BackupEntry>(value.clone())
+                    .map_err(|e| format!("Invalid backup {}: {e}", path.display()))?;
+                if update(&backup_dir, &mut entry)? {
+                    *value = serde_json::to_value(&entry).map_err(|e| e.to_string())?;
+                    changed = true;
+                }
+            }
+            if changed {
+                let payload = serde_json::to_string_pretty(&list).map_err(|e| e.to_string())?;
+                write_atomic_bytes(&path, payload.as_bytes())?;
+            }
+        }
+        if has_entries {
+            listed.push(backup_dir);
+        }
+    }
+    Ok(listed)
+}
+
+/// While backups are encrypted, seals every readable blob a backup refers
+/// to, renames it after its ciphertext and drops the content hash from the
+/// entries, then removes the readable copies. Resumes an interrupted switch
+/// to encryption.
+pub fn seal_blobs() -> Result<(), String> {
+    let Some(recipient) = backup_public_key()? else {
+        return Ok(());
+    };
+    let mut sealed_names: HashMap<String, String> = HashMap::new();
+    update_entries(|_, entry| {
+        let Some(name) = entry.blob.clone() else {
+            return Ok(false);
+        };
+        let sealed_name = match sealed_names.get(&name) {
+            Some(sealed_name) => sealed_name.clone(),
+            None => {
+                let Some((path, BlobFormat::Stored(compression))) = find_blob(&name)? else {
+                    return Ok(false);
+                };
+                let stored = fs::read(&path).map_err(|e| e.to_string())?;
+                let (sealed_name, sealed) = seal_stored(stored, compression, &recipient)?;
+                write_atomic_bytes(&format_path(&sealed_name, BlobFormat::Sealed)?, &sealed)?;
+                sealed_names.insert(name, sealed_name.clone());
+                sealed_name
+            }
+        };
+        entry.blob = Some(sealed_name);
+        entry.sha256 = None;
+        Ok(true)
+    })?;
+    if !sealed_names.is_empty() {
+        collect_garbage()?;
+    }
+    Ok(())
+}
+
+/// Replaces every sealed blob a backup refers to by its readable form, named
+/// after its content again. Blobs that fail to open are left alone; `verify`
+/// reports them.
+pub fn unseal_blobs(key: &BackupKey) -> Result<(), String> {
+    let mut opened_names: HashMap<String, String> = HashMap::new();
+    update_entries(|_, entry| {
+        let Some(name) = entry.blob.clone() else {
+            return Ok(false);
+        };
+        let hash = match opened_names.get(&name) {
+            Some(hash) => hash.clone(),
+            None => {
+                let Some((path, BlobFormat::Sealed)) = find_blob(&name)? else {
+                    return Ok(false);
+                };
+                let sealed = fs::read(&path).map_err(|e| e.to_string())?;
+                let Some((compression, stored)) = open_sealed(&sealed, key) else {
+                    return Ok(false);
+                };
+                let Ok(content) = decode(stored.clone(), compression) else {
+                    return Ok(false);
+                };
+                let hash = content_hash(&content);
+                if find_blob(&hash)?.is_none() {
+                    write_atomic_bytes(
+                        &format_path(&hash, BlobFormat::Stored(compression))?,
+                        &stored,
+                    )?;
+                }
+                opened_names.insert(name, hash.clone());
+                hash
+            }
+        };
+        entry.blob = Some(hash.clone());
+        entry.sha256 = Some(hash);
+        Ok(true)
+    })?;
+    if !opened_names.is_empty() {
+        collect_garbage()?;
+    }
+    Ok(())
+}
+
+/// Moves the per-backup file copies of older backups into the blob store.
+/// Each entry list is rewritten before the copies are removed, so an
+/// interrupted migration just resumes.
+pub fn migrate_legacy_backups() -> Result<(), String> {
+    let listed = update_entries(|backup_dir, entry| {
+        if entry.blob.is_some() {
+            return Ok(false);
+        }
+        let Some(content) = read_backup_entry(backup_dir, entry, None)? else {
+            return Ok(false);
+        };
+        let stored = store_blob(&content)?;
+        entry.blob = Some(stored.name);
+        if stored.sha256.is_none() {
+            entry.sha256 = None;
+        }
+        Ok(true)
+    })?;
+    for backup_dir in listed {
+        for child in fs::read_dir(&backup_dir).map_err(|e| e.to_string())? {
+            let child = child.map_err(|e| e.to_string())?.path();
+            if child.is_dir() {
+                fs::remove_dir_all(&child).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
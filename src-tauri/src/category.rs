@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::agents::load_agent_registry;
+use crate::backup::apply_failure_message;
+use crate::files::{remove_file_and_empty_parents, to_slash_path};
+use crate::journal::with_apply_lock;
+use crate::ledger::{load_ledgers, save_ledgers};
+use crate::mapping::{
+    default_category_mapping, load_mapping, save_mapping_inner, validate_category_name,
+    MappingConfig, SyncMode, CATEGORY_NAMES,
+};
+use crate::paths::source_root;
+use crate::sync::{category_orphan_items, remove_orphan_items};
+
+fn ensure_custom_category(mapping: &MappingConfig, category: &str) -> Result<(), String> {
+    if CATEGORY_NAMES.contains(&category) {
+        return Err(format!("Built-in category cannot be changed: {category}"));
+    }
+    if !mapping.categories.contains_key(category) {
+        return Err(format!("Unknown category: {category}"));
+    }
+    Ok(())
+}
+
+fn ensure_new_category(mapping: &MappingConfig, category: &str) -> Result<(), String> {
+    validate_category_name(category)?;
+    if CATEGORY_NAMES.contains(&category) || mapping.categories.contains_key(category) {
+        return Err(format!("Category already exists: {category}"));
+    }
+    Ok(())
+}
+
+/// A completed step of a rename, undone if a later one fails.
+enum RenameStep {
+    CreatedDir(PathBuf),
+    MovedDir {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    MovedFile {
+        from: PathBuf,
+        to: PathBuf,
+        root: PathBuf,
+    },
+}
+
+/// Moves a file, creating the destination's folders and dropping the folders
+/// it leaves empty under `root`.
+fn move_file(from: &Path, to: &Path, root: &Path) -> Result<(), String> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::rename(from, to).map_err(|e| e.to_string())?;
+    remove_file_and_empty_parents(from, root)
+}
+
+/// Undoes `steps`, newest first. Returns the steps that could not be undone.
+fn undo_rename(steps: Vec<RenameStep>) -> Vec<String> {
+    let mut errors = Vec::new();
+    for step in steps.into_iter().rev() {
+        let (path, result) = match &step {
+            RenameStep::CreatedDir(dir) => (dir, fs::remove_dir(dir).map_err(|e| e.to_string())),
+            RenameStep::MovedDir { from, to } => {
+                (to, fs::rename(to, from).map_err(|e| e.to_string()))
+            }
+            RenameStep::MovedFile { from, to, root } => (to, move_file(to, from, root)),
+        };
+        if let Err(error) = result {
+            errors.push(format!("{}: {error}", path.display()));
+        }
+    }
+    errors
+}
+
+/// `path` with its leading `from` replaced by `to`, if it lies under `from`.
+fn rebase_path(path: &str, from: &str, to: &str) -> Option<String> {
+    let rest = Path::new(path).strip_prefix(from).ok()?;
+    if rest.as_os_str().is_empty() {
+        return Some(to.to_string());
+    }
+    Some(to_slash_path(&Path::new(to).join(rest)))
+}
+
+/// Registers a custom category with the registry's default targets (the
+/// category name unless an agent says otherwise) and creates its source folder.
+pub fn add_category_inner(
+    category: &str,
+    sync_mode: Option<SyncMode>,
+) -> Result<MappingConfig, String> {
+    let mut mapping = load_mapping()?;
+    ensure_new_category(&mapping, category)?;
+    let registry = load_agent_registry()?;
+    let mut entry = default_category_mapping(category, &registry);
+    if let Some(sync_mode) = sync_mode {
+        entry.sync_mode = sync_mode;
+    }
+    mapping.categories.insert(category.to_string(), entry);
+    fs::create_dir_all(source_root()?.join(category)).map_err(|e| e.to_string())?;
+    save_mapping_inner(&mapping)?;
+    load_mapping()
+}
+
+/// Renames a custom category: moves its source folder, and for every agent
+/// whose target was still derived from the old name, moves the files
+/// AgentDock owns there to the target derived from the new name. Every move
+/// is checked before any is made, and completed ones are undone if a later
+/// step fails.
+pub fn rename_category_inner(from: &str, to: &str) -> Result<MappingConfig, String> {
+    with_apply_lock(|| rename_category(from, to))
+}
+
+fn rename_category(from: &str, to: &str) -> Result<MappingConfig, String> {
+    let mut mapping = load_mapping()?;
+    ensure_custom_category(&mapping, from)?;
+    ensure_new_category(&mapping, to)?;
+    let registry = load_agent_registry()?;
+
+    let src_root = source_root()?;
+    let (from_dir, to_dir) = (src_root.join(from), src_root.join(to));
+    let to_dir_empty = fs::read_dir(&to_dir)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(true);
+    if !to_dir_empty {
+        return Err(format!(
+            "Source folder already exists: {}",
+            to_dir.display()
+        ));
+    }
+
+    let mut entry = mapping
+        .categories
+        .remove(from)
+        .ok_or_else(|| format!("Unknown category: {from}"))?;
+    let mut retargeted = BTreeMap::new();
+    for agent in &registry.agents {
+        let (old_target, new_target) = (agent.default_target(from), agent.default_target(to));
+        if old_target != new_target && entry.target_for(&agent.id) == Some(old_target.as_str()) {
+            entry.targets.insert(agent.id.clone(), new_target.clone());
+            retargeted.insert(agent.id.clone(), (old_target, new_target));
+        }
+    }
+    mapping.categories.insert(to.to_string(), entry);
+
+    let mut ledgers = load_ledgers(&registry)?;
+    let mut original_ledgers = ledgers.clone();
+    let mut moves: Vec<(PathBuf, PathBuf, PathBuf)> = Vec::new();
+    let source_prefix = format!("{from}/");
+    for ledger in ledgers.values_mut() {
+        let Some(definition) = registry.find(&ledger.agent) else {
+            continue;
+        };
+        let agent_root = definition.root_path()?;
+        let targets = retargeted.get(&ledger.agent);
+        for (owned_path, mut owned) in std::mem::take(&mut ledger.entries) {
+            if owned.category != from {
+                ledger.entries.insert(owned_path, owned);
+                continue;
+            }
+            owned.category = to.to_string();
+            if let Some(rest) = owned.source_file.strip_prefix(&source_prefix) {
+                owned.source_file = format!("{to}/{rest}");
+            }
+            let moved_path = targets
+                .and_then(|(old, new)| rebase_path(&owned_path, old, new))
+                .filter(|_| agent_root.join(&owned_path).is_file());
+            match moved_path {
+                Some(moved_path) => {
+                    let destination = agent_root.join(&moved_path);
+                    if destination.exists() {
+                        return Err(format!(
+                            "Cannot move {owned_path} for {}: {} already exists",
+                            ledger.agent,
+                            destination.display()
+                        ));
+                    }
+                    moves.push((
+                        agent_root.join(&owned_path),
+                        destination,
+                        agent_root.clone(),
+                    ));
+                    ledger.entries.insert(moved_path, owned);
+                }
+                None => {
+                    ledger.entries.insert(owned_path, owned);
+                }
+            }
+        }
+    }
+
+    let mut steps = Vec::new();
+    let outcome = move_category(&from_dir, &to_dir, &moves, &mut steps)
+        .and_then(|()| save_ledgers(&mut ledgers, &registry))
+        .and_then(|()| save_mapping_inner(&mapping));
+    if let Err(error) = outcome {
+        let mut undo_errors = undo_rename(steps);
+        if let Err(ledger_error) = save_ledgers(&mut original_ledgers, &registry) {
+            undo_errors.push(ledger_error);
+        }
+        if undo_errors.is_empty() {
+            return Err(error);
+        }
+        return Err(format!(
+            "{error}; not rolled back: {}",
+            undo_errors.join(", ")
+        ));
+    }
+    load_mapping()
+}
+
+/// Moves the source folder and the owned target files of a rename, recording
+/// each completed step in `steps`.
+fn move_category(
+    from_dir: &Path,
+    to_dir: &Path,
+    moves: &[(PathBuf, PathBuf, PathBuf)],
+    steps: &mut Vec<RenameStep>,
+) -> Result<(), String> {
+    if from_dir.exists() {
+        if to_dir.exists() {
+            fs::remove_dir(to_dir).map_err(|e| e.to_string())?;
+        }
+        fs::rename(from_dir, to_dir).map_err(|e| e.to_string())?;
+        steps.push(RenameStep::MovedDir {
+            from: from_dir.to_path_buf(),
+            to: to_dir.to_path_buf(),
+        });
+    } else if !to_dir.exists() {
+        fs::create_dir_all(to_dir).map_err(|e| e.to_string())?;
+        steps.push(RenameStep::CreatedDir(to_dir.to_path_buf()));
+    }
+    for (current, destination, agent_root) in moves {
+        move_file(current, destination, agent_root)?;
+        steps.push(RenameStep::MovedFile {
+            from: current.clone(),
+            to: destination.clone(),
+            root: agent_root.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Drops a custom category from the mapping. Refuses while AgentDock still
+/// has content in targets for it unless `prune` is set, in which case that
+/// content is removed the way a sync removes orphaned targets: in one
+/// backed-up transaction that also saves the mapping, and never over edits
+/// made outside AgentDock. The source folder is left in place.
+pub fn remove_category_inner(category: &str, prune: bool) -> Result<MappingConfig, String> {
+    with_apply_lock(|| remove_category(category, prune))
+}
+
+fn remove_category(category: &str, prune: bool) -> Result<MappingConfig, String> {
+    let mut mapping = load_mapping()?;
+    ensure_custom_category(&mapping, category)?;
+    let registry = load_agent_registry()?;
+    mapping.categories.remove(category);
+
+    let items = category_orphan_items(&mapping, &registry, category)?;
+    if !items.is_empty() && !prune {
+        return Err(format!(
+            "Category '{category}' still has {} synced target file(s); prune them to remove it",
+            items.len()
+        ));
+    }
+    if let Some(failed) = items.iter().find(|item| item.status == "error") {
+        return Err(format!(
+            "Cannot prune {}: {}",
+            failed.id,
+            failed.error.as_deref().unwrap_or("render failed")
+        ));
+    }
+    let conflicted: Vec<&str> = items
+        .iter()
+        .filter(|item| item.conflict.is_some())
+        .map(|item| item.id.as_str())
+        .collect();
+    if !conflicted.is_empty() {
+        return Err(format!(
+            "Targets were edited outside AgentDock since the last sync; restore or remove them before pruning: {}",
+            conflicted.join(", ")
+        ));
+    }
+
+    if items.is_empty() {
+        save_mapping_inner(&mapping)?;
+    } else {
+        let result =
+            remove_orphan_items("prune", &registry, &items, || save_mapping_inner(&mapping))?;
+        if result.failure.is_some() {
+            return Err(apply_failure_message(result));
+        }
+    }
+
+    let mut ledgers = load_ledgers(&registry)?;
+    for ledger in ledgers.values_mut() {
+        ledger.entries.retain(|_, owned| owned.category != category);
+    }
+    save_ledgers(&mut ledgers, &registry)?;
+    load_mapping()
+}
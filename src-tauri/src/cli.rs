@@ -0,0 +1,812 @@
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::agents::load_agent_registry;
+use crate::mapping::{load_mapping, save_mapping_inner, SyncMode};
+use crate::retention::{self, RetentionPolicy};
+use crate::settings::{self, BackupCompression};
+use crate::types::{BackupInfo, BackupQuery, ConflictResolution, RestoreFilter, SyncItemHashes};
+use crate::{archive, backup, compare, encryption, snapshot, sync, verify, workspace};
+
+/// Exit code of `preview` when there are changes waiting to be applied.
+const EXIT_PENDING: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+const PASSPHRASE_ENV: &str = "AGENTDOCK_PASSPHRASE";
+
+#[derive(Parser)]
+#[command(name = "agentdock-cli", version, about = "Headless AgentDock sync")]
+struct Cli {
+    /// Print machine-readable JSON instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the workspace layout and bootstrap source from the agents.
+    Init,
+    /// Show what a sync would change; exits 1 when changes are pending.
+    Preview {
+        /// Print the unified diff of every pending item.
+        #[arg(long)]
+        diff: bool,
+    },
+    /// Apply pending sync items (all of them unless `--id` is given).
+    Apply {
+        /// Sync item id to apply; repeatable.
+        #[arg(long = "id")]
+        ids: Vec<String>,
+        /// Conflict resolution as `<id>=overwrite|skip|pull`; repeatable.
+        #[arg(long = "resolve", value_parser = parse_resolution)]
+        resolutions: Vec<(String, ConflictResolution)>,
+        /// Saved `preview --json` output; items that changed since are refused.
+        #[arg(long)]
+        plan: Option<PathBuf>,
+    },
+    /// Inspect and restore backups.
+    Backups {
+        #[command(subcommand)]
+        command: BackupsCommand,
+    },
+    /// Inspect and edit mapping.json.
+    Mapping {
+        #[command(subcommand)]
+        command: MappingCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupsCommand {
+    /// List backups, optionally only those matching every filter.
+    List {
+        #[arg(long)]
+        trigger: Option<String>,
+        /// Case-insensitive text in the label or note.
+        #[arg(long)]
+        label: Option<String>,
+        #[arg(long)]
+        tag: Option<String>,
+        /// Backups with an entry of this agent (or `source`).
+        #[arg(long)]
+        agent: Option<String>,
+        /// Backups with an entry whose path contains this.
+        #[arg(long)]
+        path: Option<String>,
+    },
+    Show {
+        backup_id: String,
+    },
+    /// Restore a backup, or only the entries matching the filters.
+    Restore {
+        backup_id: String,
+        /// Only entries of this agent (or `source`); repeatable.
+        #[arg(long = "agent")]
+        agents: Vec<String>,
+        /// Only this path or paths under it; repeatable.
+        #[arg(long = "path")]
+        paths: Vec<String>,
+        /// Only entries of this category; repeatable.
+        #[arg(long = "category")]
+        categories: Vec<String>,
+        /// Print what would change instead of restoring.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    Delete {
+        backup_id: String,
+    },
+    /// Back up every mapped target and the source tree under a label.
+    Snapshot {
+        #[arg(long)]
+        label: Option<String>,
+        #[arg(long)]
+        note: Option<String>,
+        /// Repeatable.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Replace a backup's label, note and tags.
+    Annotate {
+        backup_id: String,
+        #[arg(long)]
+        label: Option<String>,
+        #[arg(long)]
+        note: Option<String>,
+        /// Repeatable.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Check stored files against their recorded checksums; fails when any
+    /// are missing, truncated or modified.
+    Verify {
+        /// Defaults to every backup.
+        backup_id: Option<String>,
+    },
+    /// Compare two backups, or a backup with what a sync would write now.
+    Diff {
+        from: String,
+        /// Defaults to the result of re-syncing now.
+        to: Option<String>,
+    },
+    /// Write a backup to a portable tar archive.
+    Export {
+        backup_id: String,
+        file: PathBuf,
+    },
+    /// Add a backup from an archive written by `export`.
+    Import {
+        file: PathBuf,
+    },
+    /// Keep a backup regardless of the retention policy.
+    Pin {
+        backup_id: String,
+    },
+    Unpin {
+        backup_id: String,
+    },
+    /// Delete backups the retention policy no longer keeps.
+    Prune {
+        /// Only list what would be deleted.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Encrypt every backup, now and from now on, under a passphrase.
+    Encrypt,
+    /// Decrypt every backup and stop encrypting new ones.
+    Decrypt,
+    /// Show or change how new backup blobs are stored.
+    Compression {
+        /// none or gzip.
+        #[arg(value_parser = parse_compression)]
+        compression: Option<BackupCompression>,
+    },
+    /// Show or change the retention policy.
+    Retention {
+        #[arg(long)]
+        keep_last: Option<usize>,
+        #[arg(long)]
+        keep_days: Option<u32>,
+        #[arg(long)]
+        keep_daily: Option<u32>,
+        #[arg(long)]
+        keep_weekly: Option<u32>,
+        /// Drop every rule (keep all backups) before applying the others.
+        #[arg(long)]
+        clear: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MappingCommand {
+    Show,
+    /// Change one category's targets, sync mode and/or templating.
+    Set {
+        category: String,
+        /// Target as `<agent>=<path>`; repeatable.
+        #[arg(long = "target", value_parser = parse_target)]
+        targets: Vec<(String, String)>,
+        /// replace, append, managed or mcp.
+        #[arg(long, value_parser = parse_sync_mode)]
+        mode: Option<SyncMode>,
+        /// Render templates and includes in the category's source files.
+        #[arg(long)]
+        templates: Option<bool>,
+    },
+}
+
+fn parse_resolution(value: &str) -> Result<(String, ConflictResolution), String> {
+    let (id, resolution) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Expected <id>=<resolution>: {value}"))?;
+    let resolution = serde_json::from_value(resolution.into())
+        .map_err(|_| format!("Unknown conflict resolution: {resolution}"))?;
+    Ok((id.to_string(), resolution))
+}
+
+fn parse_target(value: &str) -> Result<(String, String), String> {
+    let (agent, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <agent>=<path>: {value}"))?;
+    Ok((agent.to_string(), path.to_string()))
+}
+
+fn parse_sync_mode(value: &str) -> Result<SyncMode, String> {
+    serde_json::from_value(value.into()).map_err(|_| format!("Unknown sync mode: {value}"))
+}
+
+fn read_line() -> Result<String, String> {
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Reads a line from stdin without echoing it when stdin is a terminal.
+#[cfg(unix)]
+fn read_hidden_line() -> Result<String, String> {
+    use std::os::fd::AsRawFd;
+
+    let fd = io::stdin().as_raw_fd();
+    let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: `tcgetattr` fills `original` when it succeeds.
+    if unsafe { libc::tcgetattr(fd, original.as_mut_ptr()) } != 0 {
+        // Not a terminal, e.g. piped input: nothing is echoed.
+        return read_line();
+    }
+    // SAFETY: initialized by the successful `tcgetattr` above.
+    let original = unsafe { original.assume_init() };
+    let mut hidden = original;
+    hidden.c_lflag &= !libc::ECHO;
+    hidden.c_lflag |= libc::ECHONL;
+    // SAFETY: `fd` is a terminal and `hidden` a valid `termios`.
+    if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &hidden) } != 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    let line = read_line();
+    // SAFETY: restores the settings read above.
+    unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &original) };
+    line
+}
+
+#[cfg(not(unix))]
+fn read_hidden_line() -> Result<String, String> {
+    use std::io::IsTerminal;
+
+    if io::stdin().is_terminal() {
+        return Err(format!(
+            "Set {PASSPHRASE_ENV} to give the backup passphrase"
+        ));
+    }
+    read_line()
+}
+
+/// The passphrase from `AGENTDOCK_PASSPHRASE`, or typed at `prompt` without
+/// being echoed.
+fn ask_passphrase(prompt: &str) -> Result<String, String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    eprint!("{prompt}");
+    io::stderr().flush().map_err(|e| e.to_string())?;
+    read_hidden_line()
+}
+
+/// The passphrase to read backups with, asked for only while they are
+/// encrypted.
+fn backup_passphrase() -> Result<Option<String>, String> {
+    if settings::load_settings()?.backup_encryption.is_none() {
+        return Ok(None);
+    }
+    ask_passphrase("Backup passphrase: ").map(Some)
+}
+
+fn parse_compression(value: &str) -> Result<BackupCompression, String> {
+    serde_json::from_value(value.into()).map_err(|_| format!("Unknown compression: {value}"))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), String> {
+    let payload = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    println!("{payload}");
+    Ok(())
+}
+
+fn preview(json: bool, diff: bool) -> Result<ExitCode, String> {
+    let preview = sync::preview_sync_inner()?;
+    let pending = preview
+        .items
+        .iter()
+        .filter(|item| item.status != "unchanged")
+        .count();
+    if json {
+        print_json(&preview)?;
+    } else {
+        for item in preview
+            .items
+            .iter()
+            .filter(|item| item.status != "unchanged")
+        {
+            let stats = &item.diff_stats;
+            println!(
+                "{:<9} {}  <- {}  (+{} -{})",
+                item.status, item.id, item.source_file, stats.added, stats.removed
+            );
+            if let Some(error) = &item.error {
+                println!("          {error}");
+            }
+            if diff {
+                print!("{}", item.diff);
+            }
+        }
+        match pending {
+            0 => println!("Everything is up to date."),
+            count => println!("{count} pending change(s)."),
+        }
+    }
+    Ok(if pending > 0 {
+        ExitCode::from(EXIT_PENDING)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Item hashes from a saved `preview --json` output.
+fn load_plan_hashes(path: &Path) -> Result<HashMap<String, SyncItemHashes>, String> {
+    #[derive(serde::Deserialize)]
+    struct PlannedItem {
+        id: String,
+        #[serde(flatten)]
+        hashes: SyncItemHashes,
+    }
+    #[derive(serde::Deserialize)]
+    struct Plan {
+        items: Vec<PlannedItem>,
+    }
+
+    let raw = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let plan = serde_json::from_str::<Plan>(&raw)
+        .map_err(|e| format!("Invalid preview plan {}: {e}", path.display()))?;
+    Ok(plan
+        .items
+        .into_iter()
+        .map(|item| (item.id, item.hashes))
+        .collect())
+}
+
+fn apply(
+    json: bool,
+    ids: Vec<String>,
+    resolutions: Vec<(String, ConflictResolution)>,
+    plan: Option<PathBuf>,
+) -> Result<ExitCode, String> {
+    let resolutions: HashMap<String, ConflictResolution> = resolutions.into_iter().collect();
+    let expected = plan.as_deref().map(load_plan_hashes).transpose()?;
+    let result = sync::apply_sync_inner(ids, None, Some(resolutions), expected)?;
+    let code = match result.failure {
+        Some(_) => ExitCode::from(EXIT_ERROR),
+        None => ExitCode::SUCCESS,
+    };
+    if json {
+        print_json(&result)?;
+        return Ok(code);
+    }
+    if let Some(failure) = &result.failure {
+        eprintln!(
+            "error: {}{}",
+            failure
+                .path
+                .as_ref()
+                .map(|path| format!("{path}: "))
+                .unwrap_or_default(),
+            failure.error
+        );
+        eprintln!("Rolled back {} file(s).", failure.rolled_back.len());
+        for error in &failure.rollback_errors {
+            eprintln!("  not restored: {error}");
+        }
+        if let Some(backup_id) = &result.backup_id {
+            eprintln!("Originals are kept in backup {backup_id}.");
+        }
+        return Ok(code);
+    }
+    for file in &result.files {
+        println!("{file}");
+    }
+    match &result.backup_id {
+        Some(backup_id) => println!(
+            "Applied {} file(s), backup {backup_id}.",
+            result.applied_count
+        ),
+        None => println!("Nothing to apply."),
+    }
+    Ok(code)
+}
+
+fn print_backup_info(info: &BackupInfo) {
+    let mut line = format!(
+        "{}  {:<8} {} file(s), {} ({} stored)",
+        info.backup_id,
+        info.trigger,
+        info.entry_count,
+        format_bytes(info.total_bytes),
+        format_bytes(info.stored_bytes)
+    );
+    if info.pinned {
+        line.push_str("  pinned");
+    }
+    if let Some(label) = &info.label {
+        line.push_str(&format!("  \"{label}\""));
+    }
+    if !info.tags.is_empty() {
+        line.push_str(&format!("  [{}]", info.tags.join(", ")));
+    }
+    println!("{line}");
+    if let Some(note) = &info.note {
+        println!("    {note}");
+    }
+}
+
+fn backups(json: bool, command: BackupsCommand) -> Result<(), String> {
+    match command {
+        BackupsCommand::List {
+            trigger,
+            label,
+            tag,
+            agent,
+            path,
+        } => {
+            let backups = snapshot::search_backups_inner(&BackupQuery {
+                trigger,
+                label,
+                tag,
+                agent,
+                path,
+            })?;
+            if json {
+                return print_json(&backups);
+            }
+            backups.iter().for_each(print_backup_info);
+        }
+        BackupsCommand::Show { backup_id } => {
+            let detail =
+                backup::get_backup_detail_inner(backup_id, backup_passphrase()?.as_deref())?;
+            if json {
+                return print_json(&detail);
+            }
+            println!("{} ({})", detail.backup_id, detail.trigger);
+            for entry in &detail.entries {
+                let state = if entry.existed_before {
+                    "existed"
+                } else {
+                    "new"
+                };
+                println!(
+                    "  {:<8} {}/{}",
+                    state, entry.agent, entry.target_relative_path
+                );
+            }
+        }
+        BackupsCommand::Restore {
+            backup_id,
+            agents,
+            paths,
+            categories,
+            dry_run,
+        } => {
+            let filter = RestoreFilter {
+                agents,
+                paths,
+                categories,
+            };
+            if dry_run {
+                let preview = backup::preview_restore_inner(
+                    backup_id,
+                    &filter,
+                    backup_passphrase()?.as_deref(),
+                )?;
+                if json {
+                    return print_json(&preview);
+                }
+                for entry in &preview.entries {
+                    println!(
+                        "{:<6} {}/{}",
+                        entry.action, entry.agent, entry.target_relative_path
+                    );
+                }
+                return Ok(());
+            }
+            let result =
+                backup::restore_backup_inner(backup_id, &filter, backup_passphrase()?.as_deref())?;
+            if json {
+                return print_json(&result);
+            }
+            println!("Restored {} file(s).", result.restored_count);
+            if let Some(backup_id) = &result.backup_id {
+                println!("Previous contents saved as backup {backup_id}.");
+            }
+        }
+        BackupsCommand::Delete { backup_id } => {
+            backup::delete_backup_inner(backup_id.clone())?;
+            if !json {
+                println!("Deleted backup {backup_id}.");
+            }
+        }
+        BackupsCommand::Verify { backup_id } => {
+            let passphrase = backup_passphrase()?;
+            let results = match backup_id {
+                Some(backup_id) => vec![verify::verify_backup_inner(
+                    &backup_id,
+                    passphrase.as_deref(),
+                )?],
+                None => verify::verify_all_backups_inner(passphrase.as_deref())?,
+            };
+            if json {
+                print_json(&results)?;
+            } else {
+                for result in &results {
+                    let state = if result.ok { "ok" } else { "DAMAGED" };
+                    println!(
+                        "{}  {state}  {} file(s) checked",
+                        result.backup_id, result.checked
+                    );
+                    for issue in &result.issues {
+                        println!(
+                            "    {:<9} {}/{}",
+                            issue.problem, issue.agent, issue.target_relative_path
+                        );
+                    }
+                }
+            }
+            let damaged = results.iter().filter(|result| !result.ok).count();
+            if damaged > 0 {
+                return Err(format!("{damaged} backup(s) failed verification"));
+            }
+        }
+        BackupsCommand::Diff { from, to } => {
+            let diff =
+                compare::diff_backups_inner(&from, to.as_deref(), backup_passphrase()?.as_deref())?;
+            if json {
+                return print_json(&diff);
+            }
+            for entry in &diff.entries {
+                let stats = &entry.diff_stats;
+                println!(
+                    "{:<8} {}/{}  (+{} -{})",
+                    entry.status,
+                    entry.agent,
+                    entry.target_relative_path,
+                    stats.added,
+                    stats.removed
+                );
+                print!("{}", entry.diff);
+            }
+            if diff.entries.is_empty() {
+                println!("No differences.");
+            }
+        }
+        BackupsCommand::Export { backup_id, file } => {
+            let path = archive::export_backup_inner(&backup_id, &file)?;
+            if !json {
+                println!("Exported backup {backup_id} to {path}.");
+            }
+        }
+        BackupsCommand::Import { file } => {
+            let info = match archive::import_backup_inner(&file, None) {
+                Err(error) if error == archive::ARCHIVE_PASSPHRASE_REQUIRED => {
+                    let passphrase = ask_passphrase("Archive passphrase: ")?;
+                    archive::import_backup_inner(&file, Some(&passphrase))?
+                }
+                result => result?,
+            };
+            if json {
+                return print_json(&info);
+            }
+            println!(
+                "Imported backup {} ({} file(s)).",
+                info.backup_id, info.entry_count
+            );
+        }
+        BackupsCommand::Snapshot { label, note, tags } => {
+            let info = snapshot::create_snapshot_inner(label, note, tags)?;
+            if json {
+                return print_json(&info);
+            }
+            print_backup_info(&info);
+        }
+        BackupsCommand::Annotate {
+            backup_id,
+            label,
+            note,
+            tags,
+        } => {
+            let info = snapshot::annotate_backup_inner(&backup_id, label, note, tags)?;
+            if json {
+                return print_json(&info);
+            }
+            print_backup_info(&info);
+        }
+        BackupsCommand::Pin { backup_id } => retention::set_backup_pinned_inner(&backup_id, true)?,
+        BackupsCommand::Unpin { backup_id } => {
+            retention::set_backup_pinned_inner(&backup_id, false)?
+        }
+        BackupsCommand::Prune { dry_run } => {
+            let result = retention::prune_backups_inner(dry_run)?;
+            if json {
+                return print_json(&result);
+            }
+            let verb = if dry_run { "Would delete" } else { "Deleted" };
+            for info in &result.pruned {
+                println!("{verb} {}  {}", info.backup_id, info.trigger);
+            }
+            println!(
+                "{verb} {} backup(s), kept {}.",
+                result.pruned.len(),
+                result.kept.len()
+            );
+        }
+        BackupsCommand::Encrypt => {
+            let passphrase = ask_passphrase("New backup passphrase: ")?;
+            if env::var_os(PASSPHRASE_ENV).is_none()
+                && ask_passphrase("Repeat the passphrase: ")? != passphrase
+            {
+                return Err("The passphrases do not match".to_string());
+            }
+            encryption::enable_backup_encryption_inner(&passphrase)?;
+            if !json {
+                println!("Backups are now encrypted.");
+            }
+        }
+        BackupsCommand::Decrypt => {
+            let passphrase = ask_passphrase("Backup passphrase: ")?;
+            encryption::disable_backup_encryption_inner(&passphrase)?;
+            if !json {
+                println!("Backups are no longer encrypted.");
+            }
+        }
+        BackupsCommand::Compression { compression } => {
+            let mut settings = settings::load_settings()?;
+            if let Some(compression) = compression {
+                settings.backup_compression = compression;
+                settings::save_settings_inner(&settings)?;
+            }
+            if json {
+                return print_json(&settings.backup_compression);
+            }
+            let name =
+                serde_json::to_value(settings.backup_compression).map_err(|e| e.to_string())?;
+            println!("{}", name.as_str().unwrap_or_default());
+        }
+        BackupsCommand::Retention {
+            keep_last,
+            keep_days,
+            keep_daily,
+            keep_weekly,
+            clear,
+        } => {
+            let mut policy = if clear {
+                RetentionPolicy::default()
+            } else {
+                retention::load_retention_policy()?
+            };
+            let edited = clear
+                || keep_last.is_some()
+                || keep_days.is_some()
+                || keep_daily.is_some()
+                || keep_weekly.is_some();
+            policy.keep_last = keep_last.or(policy.keep_last);
+            policy.keep_days = keep_days.or(policy.keep_days);
+            policy.keep_daily = keep_daily.or(policy.keep_daily);
+            policy.keep_weekly = keep_weekly.or(policy.keep_weekly);
+            if edited {
+                retention::save_retention_policy_inner(&policy)?;
+            }
+            if json {
+                return print_json(&policy);
+            }
+            let rules = [
+                ("keep-last", policy.keep_last.map(|value| value as u64)),
+                ("keep-days", policy.keep_days.map(u64::from)),
+                ("keep-daily", policy.keep_daily.map(u64::from)),
+                ("keep-weekly", policy.keep_weekly.map(u64::from)),
+            ];
+            let rules: Vec<String> = rules
+                .iter()
+                .filter_map(|(name, value)| value.map(|value| format!("{name}={value}")))
+                .collect();
+            if rules.is_empty() {
+                println!("No retention rules; every backup is kept.");
+            } else {
+                println!("{}", rules.join(" "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn mapping(json: bool, command: MappingCommand) -> Result<(), String> {
+    let mut mapping = load_mapping()?;
+    if let MappingCommand::Set {
+        category,
+        targets,
+        mode,
+        templates,
+    } = command
+    {
+        let registry = load_agent_registry()?;
+        let entry = mapping
+            .categories
+            .get_mut(&category)
+            .ok_or_else(|| format!("Unknown category: {category}"))?;
+        for (agent, path) in targets {
+            if registry.find(&agent).is_none() {
+                return Err(format!("Unsupported agent: {agent}"));
+            }
+            entry.targets.insert(agent, path);
+        }
+        if let Some(mode) = mode {
+            entry.sync_mode = mode;
+        }
+        if let Some(templates) = templates {
+            entry.templates = templates;
+        }
+        save_mapping_inner(&mapping)?;
+        mapping = load_mapping()?;
+    }
+
+    if json {
+        return print_json(&mapping);
+    }
+    for (category, entry) in &mapping.categories {
+        let mode = serde_json::to_value(&entry.sync_mode).map_err(|e| e.to_string())?;
+        let templates = if entry.templates { ", templates" } else { "" };
+        println!(
+            "{category} ({}{templates})",
+            mode.as_str().unwrap_or_default()
+        );
+        for (agent, path) in &entry.targets {
+            println!("  {agent:<8} {path}");
+        }
+    }
+    Ok(())
+}
+
+fn execute(cli: Cli) -> Result<ExitCode, String> {
+    let json = cli.json;
+    match cli.command {
+        Command::Init => {
+            let info = workspace::init_workspace_inner()?;
+            if json {
+                print_json(&info)?;
+            } else {
+                println!("Workspace ready at {}", info.app_root);
+            }
+        }
+        Command::Preview { diff } => return preview(json, diff),
+        Command::Apply {
+            ids,
+            resolutions,
+            plan,
+        } => return apply(json, ids, resolutions, plan),
+        Command::Backups { command } => backups(json, command)?,
+        Command::Mapping { command } => mapping(json, command)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn run() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+    match execute(cli) {
+        Ok(code) => code,
+        Err(error) => {
+            if json {
+                let _ = print_json(&serde_json::json!({ "error": error }));
+            } else {
+                eprintln!("error: {error}");
+            }
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
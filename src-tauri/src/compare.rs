@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::agents::load_agent_registry;
+use crate::backup::load_backup_manifest;
+use crate::blobs::read_backup_entry;
+use crate::diff::unified_diff;
+use crate::encryption::{unlock_backup_key, BackupKey};
+use crate::mapping::load_mapping;
+use crate::sync::build_sync_items;
+use crate::types::{BackupDiff, BackupDiffEntry, DiffStats};
+use crate::workspace::ensure_workspace_layout;
+
+/// `(agent, target_relative_path)`; entries of different backups line up on it.
+type EntryKey = (String, String);
+/// File contents per entry; `None` when the file does not exist.
+type Contents = BTreeMap<EntryKey, Option<Vec<u8>>>;
+
+fn read_current(path: &Path) -> Result<Option<Vec<u8>>, String> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    fs::read(path).map(Some).map_err(|e| e.to_string())
+}
+
+/// Backed-up contents of every entry, with the entries' target paths.
+fn backup_contents(
+    backup_id: &str,
+    backup_key: Option<&BackupKey>,
+) -> Result<(Contents, BTreeMap<EntryKey, String>), String> {
+    let (backup_dir, manifest) =
+        load_backup_manifest(backup_id).map_err(|_| format!("Backup not found: {backup_id}"))?;
+    let mut contents = BTreeMap::new();
+    let mut targets = BTreeMap::new();
+    for entry in &manifest.entries {
+        let key = (entry.agent.clone(), entry.target_relative_path.clone());
+        contents.insert(
+            key.clone(),
+            read_backup_entry(&backup_dir, entry, backup_key)?,
+        );
+        targets.insert(key, entry.target_absolute_path.clone());
+    }
+    Ok((contents, targets))
+}
+
+/// What the files of `targets`, plus every file a sync would change, would
+/// contain right after a sync. Items that cannot be rendered keep their
+/// current content. Also returns the current content of the files a sync
+/// would change that `targets` does not list, as the other side to diff them
+/// against.
+fn resync_contents(targets: &BTreeMap<EntryKey, String>) -> Result<(Contents, Contents), String> {
+    let mapping = load_mapping()?;
+    let registry = load_agent_registry()?;
+    let mut contents = BTreeMap::new();
+    let mut current = BTreeMap::new();
+    for item in build_sync_items(&mapping, &registry)? {
+        let key = (item.agent.clone(), item.target_relative_path.clone());
+        if item.status == "unchanged" && !targets.contains_key(&key) {
+            continue;
+        }
+        let target = Path::new(&item.target_absolute_path);
+        if !targets.contains_key(&key) {
+            let existing = match item.status.as_str() {
+                "create" => None,
+                _ => read_current(target)?,
+            };
+            current.insert(key.clone(), existing);
+        }
+        let content = match item.status.as_str() {
+            "delete" => None,
+            "error" => read_current(target)?,
+            _ => Some(item.after.into_bytes()),
+        };
+        contents.insert(key, content);
+    }
+    for (key, target) in targets {
+        if !contents.contains_key(key) {
+            contents.insert(key.clone(), read_current(Path::new(target))?);
+        }
+    }
+    Ok((contents, current))
+}
+
+/// Compares the files both sides recorded. A file only one side recorded is
+/// reported as `unrecorded`: that side not listing it says nothing about
+/// whether it existed, so it is not classified as added or removed.
+fn diff_contents(from: &Contents, to: &Contents) -> Vec<BackupDiffEntry> {
+    let keys: BTreeSet<&EntryKey> = from.keys().chain(to.keys()).collect();
+    let mut entries = Vec::new();
+    for key in keys {
+        let (before, after) = match (from.get(key), to.get(key)) {
+            (Some(before), Some(after)) => (before.as_deref(), after.as_deref()),
+            _ => {
+                let (agent, relative) = key;
+                entries.push(BackupDiffEntry {
+                    agent: agent.clone(),
+                    target_relative_path: relative.clone(),
+                    status: "unrecorded".to_string(),
+                    diff: String::new(),
+                    diff_stats: DiffStats::default(),
+                });
+                continue;
+            }
+        };
+        let status = match (before, after) {
+            (None, None) => continue,
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            (Some(before), Some(after)) if before == after => continue,
+            (Some(_), Some(_)) => "modified",
+        };
+        let (agent, relative) = key;
+        let (diff, diff_stats) = unified_diff(
+            &String::from_utf8_lossy(before.unwrap_or_default()),
+            &String::from_utf8_lossy(after.unwrap_or_default()),
+            &format!("{agent}/{relative}"),
+        );
+        entries.push(BackupDiffEntry {
+            agent: agent.clone(),
+            target_relative_path: relative.clone(),
+            status: status.to_string(),
+            diff,
+            diff_stats,
+        });
+    }
+    entries
+}
+
+/// Compares backup `from` with backup `to`, or with the state a sync would
+/// leave behind right now when `to` is `None`.
+pub fn diff_backups_inner(
+    from: &str,
+    to: Option<&str>,
+    passphrase: Option<&str>,
+) -> Result<BackupDiff, String> {
+    ensure_workspace_layout()?;
+    let backup_key = unlock_backup_key(passphrase)?;
+    let (mut from_contents, targets) = backup_contents(from, backup_key.as_ref())?;
+    let to_contents = match to {
+        Some(to) => backup_contents(to, backup_key.as_ref())?.0,
+        None => {
+            // Files the backup did not record are diffed from what they hold now.
+            let (planned, current) = resync_contents(&targets)?;
+            from_contents.extend(current);
+            planned
+        }
+    };
+    Ok(BackupDiff {
+        from: from.to_string(),
+        to: to.map(str::to_string),
+        entries: diff_contents(&from_contents, &to_contents),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(entries: &[(&str, Option<&str>)]) -> Contents {
+        entries
+            .iter()
+            .map(|(relative, content)| {
+                (
+                    ("claude".to_string(), relative.to_string()),
+                    content.map(|content| content.as_bytes().to_vec()),
+                )
+            })
+            .collect()
+    }
+
+    fn statuses(entries: &[BackupDiffEntry]) -> Vec<(&str, &str)> {
+        entries
+            .iter()
+            .map(|entry| (entry.target_relative_path.as_str(), entry.status.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn classifies_changes_between_recorded_entries() {
+        let from = contents(&[
+            ("added.md", None),
+            ("absent.md", None),
+            ("modified.md", Some("a\n")),
+            ("removed.md", Some("gone\n")),
+            ("same.md", Some("same\n")),
+        ]);
+        let to = contents(&[
+            ("added.md", Some("new\n")),
+            ("absent.md", None),
+            ("modified.md", Some("b\n")),
+            ("removed.md", None),
+            ("same.md", Some("same\n")),
+        ]);
+        let entries = diff_contents(&from, &to);
+        assert_eq!(
+            statuses(&entries),
+            [
+                ("added.md", "added"),
+                ("modified.md", "modified"),
+                ("removed.md", "removed"),
+            ]
+        );
+        assert_eq!(entries[1].diff_stats.added, 1);
+        assert_eq!(entries[1].diff_stats.removed, 1);
+    }
+
+    #[test]
+    fn reports_entries_recorded_on_one_side_as_unrecorded() {
+        let from = contents(&[("only-from.md", Some("x\n")), ("gone.md", None)]);
+        let to = contents(&[("only-to.md", Some("y\n"))]);
+        let entries = diff_contents(&from, &to);
+        assert_eq!(
+            statuses(&entries),
+            [
+                ("gone.md", "unrecorded"),
+                ("only-from.md", "unrecorded"),
+                ("only-to.md", "unrecorded"),
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.diff.is_empty()));
+    }
+}
@@ -0,0 +1,39 @@
+use similar::{ChangeTag, TextDiff};
+
+use crate::files::content_hash;
+use crate::types::{DiffStats, SyncItem};
+
+const CONTEXT_LINES: usize = 3;
+
+/// Line-based unified diff of `before` → `after` with `a/` and `b/` headers
+/// for `path`. Empty when nothing changed.
+pub fn unified_diff(before: &str, after: &str, path: &str) -> (String, DiffStats) {
+    if before == after {
+        return (String::new(), DiffStats::default());
+    }
+    let diff = TextDiff::from_lines(before, after);
+    let mut stats = DiffStats::default();
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => stats.added += 1,
+            ChangeTag::Delete => stats.removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    let mut unified = diff.unified_diff();
+    unified
+        .context_radius(CONTEXT_LINES)
+        .header(&format!("a/{path}"), &format!("b/{path}"));
+    stats.hunks = unified.iter_hunks().count();
+    (unified.to_string(), stats)
+}
+
+/// Fills in what a preview ships instead of the full contents: hashes of
+/// `before`/`after` and the diff between them.
+pub fn summarize_item(item: &mut SyncItem) {
+    item.before_hash = content_hash(item.before.as_bytes());
+    item.after_hash = content_hash(item.after.as_bytes());
+    let (diff, stats) = unified_diff(&item.before, &item.after, &item.target_relative_path);
+    item.diff = diff;
+    item.diff_stats = stats;
+}
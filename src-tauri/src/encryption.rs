@@ -0,0 +1,165 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::blobs::{seal_blobs, unseal_blobs};
+use crate::journal::with_apply_lock;
+use crate::settings::{load_settings, write_settings, EncryptionSettings};
+
+pub const PASSPHRASE_REQUIRED: &str = "Backups are encrypted; enter the backup passphrase";
+const MIN_PASSPHRASE_LEN: usize = 8;
+const SEAL_MAGIC: &[u8; 4] = b"ADE1";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// The unlocked private key of encrypted backups.
+pub struct BackupKey {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|e| e.to_string())?;
+    let mut data = nonce.to_vec();
+    data.extend(ciphertext);
+    Ok(data)
+}
+
+/// `None` when `data` was not encrypted with `key` or has been altered.
+fn decrypt(key: &[u8; KEY_LEN], data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], String> {
+    let mut key = [0u8; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn blob_key(shared: &[u8; 32], ephemeral: &PublicKey, recipient: &PublicKey) -> [u8; KEY_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"agentdock-backup-blob");
+    hasher.update(shared);
+    hasher.update(ephemeral.as_bytes());
+    hasher.update(recipient.as_bytes());
+    hasher.finalize().into()
+}
+
+fn decode_key(value: &str) -> Result<[u8; 32], String> {
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| "Invalid backup encryption settings".to_string())
+}
+
+/// Encrypts `content` to `recipient` with a fresh ephemeral key, so it can
+/// be written without the passphrase.
+pub fn seal(content: &[u8], recipient: &PublicKey) -> Result<Vec<u8>, String> {
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(recipient);
+    let key = blob_key(shared.as_bytes(), &ephemeral_public, recipient);
+    let mut sealed = SEAL_MAGIC.to_vec();
+    sealed.extend_from_slice(ephemeral_public.as_bytes());
+    sealed.extend(encrypt(&key, content)?);
+    Ok(sealed)
+}
+
+/// `None` when `sealed` was not sealed to `key` or has been altered.
+pub fn open(sealed: &[u8], key: &BackupKey) -> Option<Vec<u8>> {
+    let rest = sealed.strip_prefix(SEAL_MAGIC)?;
+    let (ephemeral, data) = rest.split_at_checked(32)?;
+    let ephemeral = PublicKey::from(<[u8; 32]>::try_from(ephemeral).ok()?);
+    let shared = key.secret.diffie_hellman(&ephemeral);
+    decrypt(&blob_key(shared.as_bytes(), &ephemeral, &key.public), data)
+}
+
+/// The key new blobs are sealed to; `None` while backups are not encrypted.
+pub fn backup_public_key() -> Result<Option<PublicKey>, String> {
+    load_settings()?
+        .backup_encryption
+        .map(|encryption| decode_key(&encryption.public_key).map(PublicKey::from))
+        .transpose()
+}
+
+/// Unwraps the private key of `encryption` with `passphrase`.
+pub fn unlock_key(encryption: &EncryptionSettings, passphrase: &str) -> Result<BackupKey, String> {
+    let salt = hex::decode(&encryption.salt).map_err(|e| e.to_string())?;
+    let wrapped = hex::decode(&encryption.wrapped_key).map_err(|e| e.to_string())?;
+    let secret = decrypt(&derive_key(passphrase, &salt)?, &wrapped)
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| "Wrong backup passphrase".to_string())?;
+    let secret = StaticSecret::from(secret);
+    let public = PublicKey::from(&secret);
+    if public.as_bytes() != &decode_key(&encryption.public_key)? {
+        return Err("Invalid backup encryption settings".to_string());
+    }
+    Ok(BackupKey { secret, public })
+}
+
+/// Unlocks the backup key. `None` while backups are not encrypted; fails
+/// without a passphrase or with a wrong one when they are.
+pub fn unlock_backup_key(passphrase: Option<&str>) -> Result<Option<BackupKey>, String> {
+    let Some(encryption) = load_settings()?.backup_encryption else {
+        return Ok(None);
+    };
+    let passphrase = passphrase
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| PASSPHRASE_REQUIRED.to_string())?;
+    unlock_key(&encryption, passphrase).map(Some)
+}
+
+/// Turns on backup encryption and encrypts every existing backup.
+pub fn enable_backup_encryption_inner(passphrase: &str) -> Result<(), String> {
+    let mut settings = load_settings()?;
+    if settings.backup_encryption.is_some() {
+        return Err("Backups are already encrypted".to_string());
+    }
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!(
+            "The passphrase must be at least {MIN_PASSPHRASE_LEN} characters"
+        ));
+    }
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let wrapped = encrypt(&derive_key(passphrase, &salt)?, secret.as_bytes())?;
+    settings.backup_encryption = Some(EncryptionSettings {
+        public_key: hex::encode(public.as_bytes()),
+        salt: hex::encode(salt),
+        wrapped_key: hex::encode(wrapped),
+    });
+    with_apply_lock(|| {
+        write_settings(&settings)?;
+        seal_blobs()
+    })
+}
+
+/// Decrypts every backup and turns backup encryption off.
+pub fn disable_backup_encryption_inner(passphrase: &str) -> Result<(), String> {
+    let key = unlock_backup_key(Some(passphrase))?
+        .ok_or_else(|| "Backups are not encrypted".to_string())?;
+    with_apply_lock(|| {
+        unseal_blobs(&key)?;
+        let mut settings = load_settings()?;
+        settings.backup_encryption = None;
+        write_settings(&settings)
+    })
+}
@@ -1,3 +1,4 @@
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Component, Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -10,6 +11,11 @@ pub fn now_millis() -> Result<u128, String> {
         .as_millis())
 }
 
+/// Lowercase hex SHA-256 of `data`.
+pub fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
 pub fn to_slash_path(path: &Path) -> String {
     path.to_string_lossy().replace('\\', "/")
 }
@@ -79,6 +85,28 @@ pub fn write_atomic_bytes(path: &Path, data: &[u8]) -> Result<(), String> {
     Ok(())
 }
 
+/// Removes `path`, then any parent directories left empty, stopping at `stop_at`.
+pub fn remove_file_and_empty_parents(path: &Path, stop_at: &Path) -> Result<(), String> {
+    if path.exists() {
+        fs::remove_file(path).map_err(|e| e.to_string())?;
+    }
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == stop_at || !dir.starts_with(stop_at) {
+            break;
+        }
+        let is_empty = fs::read_dir(dir)
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(false);
+        if !is_empty {
+            break;
+        }
+        fs::remove_dir(dir).map_err(|e| e.to_string())?;
+        current = dir.parent();
+    }
+    Ok(())
+}
+
 pub fn read_text(path: &Path) -> Result<String, String> {
     match fs::read_to_string(path) {
         Ok(text) => Ok(text),
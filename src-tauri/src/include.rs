@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use crate::files::{normalize_relative_path, read_text, to_slash_path};
+use crate::paths::source_root;
+
+const INCLUDE_DIRECTIVE: &str = "@include ";
+const FRAGMENTS_DIR_NAME: &str = "fragments";
+
+/// Files under the top-level `fragments/` directory of a templated category
+/// (`relative_path` is relative to the category) are only pulled in through
+/// `@include` and never synced as targets of their own. A `fragments/`
+/// directory deeper down, e.g. inside a skill, is ordinary content.
+pub fn is_fragment(relative_path: &str, templates: bool) -> bool {
+    templates
+        && Path::new(relative_path)
+            .components()
+            .next()
+            .is_some_and(|component| component.as_os_str() == FRAGMENTS_DIR_NAME)
+}
+
+fn include_target(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix(INCLUDE_DIRECTIVE)
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+}
+
+pub fn has_includes(text: &str) -> bool {
+    text.lines().any(|line| include_target(line).is_some())
+}
+
+/// Resolves an include path against the including file's category first,
+/// then against the source root. Returns the path relative to source root.
+fn resolve_include(path: &str, category: &str) -> Result<String, String> {
+    let relative =
+        normalize_relative_path(path).map_err(|e| format!("Invalid include path '{path}': {e}"))?;
+    let src_root = source_root()?;
+    let in_category = Path::new(category).join(&relative);
+    if src_root.join(&in_category).is_file() {
+        return Ok(to_slash_path(&in_category));
+    }
+    if src_root.join(&relative).is_file() {
+        return Ok(to_slash_path(&relative));
+    }
+    Err(format!("Included file not found: {path}"))
+}
+
+fn expand(
+    text: &str,
+    category: &str,
+    stack: &mut Vec<String>,
+    contributors: &mut Vec<String>,
+) -> Result<String, String> {
+    let mut output = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let Some(path) = include_target(line) else {
+            output.push_str(line);
+            continue;
+        };
+        let resolved = resolve_include(path, category)?;
+        if stack.contains(&resolved) {
+            return Err(format!(
+                "Include cycle: {} -> {resolved}",
+                stack.join(" -> ")
+            ));
+        }
+        if !contributors.contains(&resolved) {
+            contributors.push(resolved.clone());
+        }
+
+        let fragment = read_text(&source_root()?.join(&resolved))?;
+        let fragment_category = resolved.split('/').next().unwrap_or(category).to_string();
+        stack.push(resolved);
+        let expanded = expand(&fragment, &fragment_category, stack, contributors)?;
+        stack.pop();
+
+        output.push_str(&expanded);
+        if line.ends_with('\n') && !expanded.is_empty() && !expanded.ends_with('\n') {
+            output.push('\n');
+        }
+    }
+    Ok(output)
+}
+
+/// Replaces every `@include <path>` line of `origin` (relative to source root)
+/// with the referenced file, recursively. Included files are appended to
+/// `contributors`.
+pub fn expand_includes(
+    text: &str,
+    category: &str,
+    origin: &str,
+    contributors: &mut Vec<String>,
+) -> Result<String, String> {
+    let mut stack = vec![origin.to_string()];
+    expand(text, category, &mut stack, contributors)
+}
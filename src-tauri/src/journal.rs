@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+use crate::backup::{backup_entry, create_backup_dir, write_backup_manifest};
+use crate::blobs::{collect_garbage, read_verified_entry};
+use crate::files::{now_millis, remove_file_and_empty_parents, write_atomic_bytes};
+use crate::paths::{apply_lock_path, backups_root, resolve_scope_base};
+use crate::retention::enforce_retention;
+use crate::types::{ApplyFailure, ApplySyncResult, BackupEntry, BackupManifest};
+
+const JOURNAL_FILE_NAME: &str = "journal.json";
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+/// Trigger of a backup kept because an apply could not be fully rolled back.
+pub const ROLLBACK_TRIGGER: &str = "rollback";
+const APPLY_IN_PROGRESS: &str =
+    "Another AgentDock apply is in progress; try again once it finishes";
+
+/// Written next to the backup copies before an apply changes anything and
+/// removed once the manifest exists. A leftover journal means the apply was
+/// interrupted.
+#[derive(Debug, Serialize, Deserialize)]
+struct ApplyJournal {
+    backup_id: String,
+    trigger: String,
+    started_at: u128,
+    entries: Vec<BackupEntry>,
+}
+
+/// A file an apply is about to change.
+pub struct JournalTarget {
+    /// Agent id, or `source` for files under the source root.
+    pub agent: String,
+    pub relative: String,
+    pub absolute: PathBuf,
+}
+
+/// An exclusive lock on `apply.lock`, held while an apply or the recovery of
+/// interrupted ones changes files, so one process never rolls back another's
+/// apply midway, and while blobs are stored or collected, so garbage
+/// collection never drops blobs a backup is about to reference. The OS drops
+/// it with the process.
+struct ApplyLock {
+    /// `None` when nested in a lock this thread already holds.
+    _file: Option<File>,
+}
+
+thread_local! {
+    /// How many `ApplyLock`s this thread holds.
+    static LOCK_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+impl ApplyLock {
+    /// Takes the lock, or returns `None` while another apply holds it. Nested
+    /// calls on the thread that holds it share that lock, so an apply can run
+    /// inside `with_apply_lock`.
+    fn try_acquire() -> Result<Option<Self>, String> {
+        if Self::held_by_this_thread() {
+            LOCK_DEPTH.with(|depth| depth.set(depth.get() + 1));
+            return Ok(Some(ApplyLock { _file: None }));
+        }
+        let path = apply_lock_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        match file.try_lock() {
+            Ok(()) => {
+                LOCK_DEPTH.with(|depth| depth.set(1));
+                Ok(Some(ApplyLock { _file: Some(file) }))
+            }
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(error)) => Err(error.to_string()),
+        }
+    }
+
+    fn held_by_this_thread() -> bool {
+        LOCK_DEPTH.with(|depth| depth.get() > 0)
+    }
+}
+
+impl Drop for ApplyLock {
+    fn drop(&mut self) {
+        LOCK_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+pub struct ApplyTransaction {
+    lock: ApplyLock,
+    backup_dir: PathBuf,
+    journal: ApplyJournal,
+    /// What each target held before, for rolling back without reading the
+    /// backup copies, which may be sealed.
+    originals: Vec<Option<Vec<u8>>>,
+    /// Number of entries whose target may already have been changed.
+    touched: usize,
+    failed_path: Option<String>,
+    restored_from: Option<String>,
+}
+
+fn write_journal(backup_dir: &Path, journal: &ApplyJournal) -> Result<(), String> {
+    let payload = serde_json::to_string_pretty(journal).map_err(|e| e.to_string())?;
+    write_atomic_bytes(&backup_dir.join(JOURNAL_FILE_NAME), payload.as_bytes())
+}
+
+/// Puts the target of `entry` back to `original`, or removes it when it did
+/// not exist.
+fn put_back(entry: &BackupEntry, original: Option<&[u8]>) -> Result<(), String> {
+    let target = PathBuf::from(&entry.target_absolute_path);
+    if let Some(original) = original {
+        return write_atomic_bytes(&target, original);
+    }
+    match resolve_scope_base(&entry.agent) {
+        Ok(root) => remove_file_and_empty_parents(&target, &root),
+        Err(_) if target.exists() => fs::remove_file(&target).map_err(|e| e.to_string()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Puts `entries` back to what `original` says they held, newest first.
+/// Returns the restored paths and the failures.
+fn restore_entries(
+    entries: &[BackupEntry],
+    original: impl Fn(usize, &BackupEntry) -> Result<Option<Vec<u8>>, String>,
+) -> (Vec<String>, Vec<String>) {
+    let mut restored = Vec::new();
+    let mut errors = Vec::new();
+    for (index, entry) in entries.iter().enumerate().rev() {
+        match original(index, entry).and_then(|content| put_back(entry, content.as_deref())) {
+            Ok(()) => restored.push(entry.target_absolute_path.clone()),
+            Err(error) => errors.push(format!("{}: {error}", entry.target_absolute_path)),
+        }
+    }
+    (restored, errors)
+}
+
+/// Finalizes a rolled-back backup: dropped when every file was restored,
+/// otherwise kept (with a manifest, so it can be restored by hand).
+fn settle_rollback(
+    backup_dir: &Path,
+    journal: &ApplyJournal,
+    errors: &[String],
+) -> Result<Option<String>, String> {
+    if errors.is_empty() {
+        fs::remove_dir_all(backup_dir).map_err(|e| e.to_string())?;
+        collect_garbage()?;
+        return Ok(None);
+    }
+    write_backup_manifest(
+        backup_dir,
+        &BackupManifest::new(
+            journal.backup_id.clone(),
+            journal.started_at,
+            ROLLBACK_TRIGGER,
+            journal.entries.clone(),
+        ),
+    )?;
+    fs::remove_file(backup_dir.join(JOURNAL_FILE_NAME)).map_err(|e| e.to_string())?;
+    Ok(Some(journal.backup_id.clone()))
+}
+
+impl ApplyTransaction {
+    /// Takes the apply lock, backs up every target and writes the journal.
+    /// Nothing is changed yet.
+    pub fn begin(trigger: &str, targets: &[JournalTarget]) -> Result<Self, String> {
+        let lock = ApplyLock::try_acquire()?.ok_or_else(|| APPLY_IN_PROGRESS.to_string())?;
+        let (backup_id, backup_dir) = create_backup_dir()?;
+        let mut originals = Vec::new();
+        let prepared = targets
+            .iter()
+            .map(|target| {
+                let original = if target.absolute.exists() {
+                    Some(fs::read(&target.absolute).map_err(|e| e.to_string())?)
+                } else {
+                    None
+                };
+                let entry = backup_entry(
+                    &target.agent,
+                    &target.relative,
+                    &target.absolute,
+                    original.as_deref(),
+                )?;
+                originals.push(original);
+                Ok(entry)
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .and_then(|entries| {
+                let journal = ApplyJournal {
+                    backup_id,
+                    trigger: trigger.to_string(),
+                    started_at: now_millis()?,
+                    entries,
+                };
+                write_journal(&backup_dir, &journal)?;
+                Ok(journal)
+            });
+        match prepared {
+            Ok(journal) => Ok(ApplyTransaction {
+                lock,
+                backup_dir,
+                journal,
+                originals,
+                touched: 0,
+                failed_path: None,
+                restored_from: None,
+            }),
+            Err(error) => {
+                let _ = fs::remove_dir_all(&backup_dir);
+                let _ = collect_garbage();
+                Err(error)
+            }
+        }
+    }
+
+    /// Marks the backup as taken before restoring `backup_id`.
+    pub fn restoring(mut self, backup_id: &str) -> Self {
+        self.restored_from = Some(backup_id.to_string());
+        self
+    }
+
+    /// Runs `change` for the next target, in the order given to `begin`.
+    pub fn step(&mut self, change: impl FnOnce() -> Result<(), String>) -> Result<(), String> {
+        let path = self
+            .journal
+            .entries
+            .get(self.touched)
+            .map(|entry| entry.target_absolute_path.clone())
+            .ok_or_else(|| "Apply changed more files than it backed up".to_string())?;
+        self.touched += 1;
+        change().inspect_err(|_| self.failed_path = Some(path))
+    }
+
+    /// Writes the manifest, with `snapshots` (backed-up files that were not
+    /// changed) appended, and drops the journal. Returns the backup id.
+    pub fn commit(self, snapshots: Vec<BackupEntry>) -> Result<String, String> {
+        self.finish(snapshots).map(|(backup_id, _)| backup_id)
+    }
+
+    /// Like `commit`, then applies the retention policy before the lock is
+    /// released, so pruning never races another apply's new blobs.
+    pub fn commit_and_prune(self, snapshots: Vec<BackupEntry>) -> Result<String, String> {
+        let (backup_id, _lock) = self.finish(snapshots)?;
+        enforce_retention();
+        Ok(backup_id)
+    }
+
+    fn finish(self, snapshots: Vec<BackupEntry>) -> Result<(String, ApplyLock), String> {
+        let mut entries = self.journal.entries;
+        entries.extend(snapshots);
+        write_backup_manifest(
+            &self.backup_dir,
+            &BackupManifest {
+                restored_from: self.restored_from,
+                ..BackupManifest::new(
+                    self.journal.backup_id.clone(),
+                    now_millis()?,
+                    &self.journal.trigger,
+                    entries,
+                )
+            },
+        )?;
+        fs::remove_file(self.backup_dir.join(JOURNAL_FILE_NAME)).map_err(|e| e.to_string())?;
+        Ok((self.journal.backup_id, self.lock))
+    }
+
+    /// Restores every target touched so far and reports why the apply failed.
+    pub fn rollback(self, error: String) -> ApplySyncResult {
+        let touched = &self.journal.entries[..self.touched];
+        let (rolled_back, mut rollback_errors) =
+            restore_entries(touched, |index, _| Ok(self.originals[index].clone()));
+        let backup_id = settle_rollback(&self.backup_dir, &self.journal, &rollback_errors)
+            .unwrap_or_else(|settle_error| {
+                rollback_errors.push(settle_error);
+                Some(self.journal.backup_id.clone())
+            });
+        ApplySyncResult {
+            backup_id,
+            applied_count: 0,
+            files: Vec::new(),
+            failure: Some(ApplyFailure {
+                path: self.failed_path,
+                error,
+                rolled_back,
+                rollback_errors,
+            }),
+        }
+    }
+}
+
+/// Runs `change` under the apply lock, e.g. to rewrite backups no apply may
+/// be adding to meanwhile. Fails while an apply is in progress.
+pub fn with_apply_lock<T>(change: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    let _lock = ApplyLock::try_acquire()?.ok_or_else(|| APPLY_IN_PROGRESS.to_string())?;
+    change()
+}
+
+/// Settles applies interrupted by a crash: one whose manifest was written
+/// only missed dropping its journal; any other is rolled back. Skipped while
+/// another process holds the apply lock, since its journal is still live.
+/// Encrypted backup copies cannot be read here, so an apply that needs them
+/// is kept as a `rollback` backup to restore with the passphrase. Also
+/// skipped inside a lock this thread holds, whose apply may be midway.
+pub fn recover_interrupted_applies() -> Result<(), String> {
+    if ApplyLock::held_by_this_thread() {
+        return Ok(());
+    }
+    let Some(_lock) = ApplyLock::try_acquire()? else {
+        return Ok(());
+    };
+    let root = backups_root()?;
+    if !root.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(&root).map_err(|e| e.to_string())? {
+        let backup_dir = entry.map_err(|e| e.to_string())?.path();
+        let journal_path = backup_dir.join(JOURNAL_FILE_NAME);
+        if !journal_path.is_file() {
+            continue;
+        }
+        if backup_dir.join(MANIFEST_FILE_NAME).exists() {
+            fs::remove_file(&journal_path).map_err(|e| e.to_string())?;
+            continue;
+        }
+        let raw = fs::read_to_string(&journal_path).map_err(|e| e.to_string())?;
+        let journal = serde_json::from_str::<ApplyJournal>(&raw)
+            .map_err(|e| format!("Invalid apply journal {}: {e}", journal_path.display()))?;
+        let (_, errors) = restore_entries(&journal.entries, |_, entry| {
+            entry
+                .existed_before
+                .then(|| read_verified_entry(&backup_dir, entry, None))
+                .transpose()
+        });
+        settle_rollback(&backup_dir, &journal, &errors)?;
+    }
+    Ok(())
+}
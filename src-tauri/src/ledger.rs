@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::agents::AgentRegistry;
+use crate::files::write_atomic_bytes;
+use crate::paths::ledger_root;
+
+pub const LEDGER_VERSION: u32 = 1;
+
+/// A target file AgentDock wrote for an agent.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LedgerEntry {
+    pub category: String,
+    pub source_file: String,
+    pub updated_at: u128,
+    /// SHA-256 of the bytes AgentDock last wrote; a mismatch means the file
+    /// was edited outside AgentDock.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Set for shared MCP configs: the server names AgentDock merged in. Such
+    /// files hold user settings too, so pruning only removes those servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mcp_servers: Option<Vec<String>>,
+    /// Set for `managed` targets: AgentDock only owns its marker block, so
+    /// pruning removes that block and keeps the rest of the file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub managed: bool,
+    /// The JSON/TOML keys a keyed merge wrote into a `managed` target; keys
+    /// that leave the source are removed from the target again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub managed_keys: Vec<Vec<String>>,
+}
+
+/// What applying a sync item records in its agent's ownership ledger.
+#[derive(Debug, Clone, Default)]
+pub enum Ownership {
+    /// The entry is left as it is, e.g. for `append` targets, which carry the
+    /// user's own content.
+    #[default]
+    Unowned,
+    /// AgentDock wrote the whole file.
+    File,
+    /// AgentDock wrote only its part of a `managed` target: the marker block,
+    /// or these keys of a keyed merge.
+    Managed(Vec<Vec<String>>),
+    /// AgentDock merged these MCP server names into a shared config.
+    Mcp(Vec<String>),
+    /// AgentDock's file or part of it was removed; the entry is dropped.
+    Released,
+}
+
+/// Per-agent record of the target files AgentDock owns, keyed by
+/// `target_relative_path`. Only owned files are ever pruned.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OwnershipLedger {
+    pub version: u32,
+    pub agent: String,
+    pub entries: BTreeMap<String, LedgerEntry>,
+}
+
+impl OwnershipLedger {
+    pub fn empty(agent: &str) -> Self {
+        OwnershipLedger {
+            version: LEDGER_VERSION,
+            agent: agent.to_string(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Accepts the target's current content as the drift baseline, e.g. after
+    /// it was pulled into source.
+    pub fn adopt_hash(&mut self, target_relative_path: &str, hash: String, now: u128) {
+        if let Some(entry) = self.entries.get_mut(target_relative_path) {
+            entry.hash = Some(hash);
+            entry.updated_at = now;
+        }
+    }
+}
+
+pub fn load_ledgers(registry: &AgentRegistry) -> Result<BTreeMap<String, OwnershipLedger>, String> {
+    let mut ledgers = BTreeMap::new();
+    for definition in &registry.agents {
+        ledgers.insert(definition.id.clone(), load_ledger(&definition.id)?);
+    }
+    Ok(ledgers)
+}
+
+/// Returns the cached ledger for `agent`, loading it on first use.
+pub fn ledger_for<'a>(
+    ledgers: &'a mut BTreeMap<String, OwnershipLedger>,
+    agent: &str,
+) -> Result<&'a mut OwnershipLedger, String> {
+    if !ledgers.contains_key(agent) {
+        ledgers.insert(agent.to_string(), load_ledger(agent)?);
+    }
+    ledgers
+        .get_mut(agent)
+        .ok_or_else(|| format!("Missing ownership ledger for agent: {agent}"))
+}
+
+/// Saves every touched ledger, dropping entries whose target no longer exists.
+pub fn save_ledgers(
+    ledgers: &mut BTreeMap<String, OwnershipLedger>,
+    registry: &AgentRegistry,
+) -> Result<(), String> {
+    for ledger in ledgers.values_mut() {
+        if let Some(definition) = registry.find(&ledger.agent) {
+            let agent_root = definition.root_path()?;
+            ledger
+                .entries
+                .retain(|owned_path, _| agent_root.join(owned_path).is_file());
+        }
+        save_ledger(ledger)?;
+    }
+    Ok(())
+}
+
+fn ledger_path(agent: &str) -> Result<PathBuf, String> {
+    Ok(ledger_root()?.join(format!("{agent}.json")))
+}
+
+pub fn load_ledger(agent: &str) -> Result<OwnershipLedger, String> {
+    let path = ledger_path(agent)?;
+    if !path.exists() {
+        return Ok(OwnershipLedger::empty(agent));
+    }
+    let raw = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str::<OwnershipLedger>(&raw)
+        .map_err(|e| format!("Invalid ownership ledger {}: {e}", path.display()))
+}
+
+pub fn save_ledger(ledger: &OwnershipLedger) -> Result<(), String> {
+    let path = ledger_path(&ledger.agent)?;
+    let payload = serde_json::to_string_pretty(ledger).map_err(|e| e.to_string())?;
+    write_atomic_bytes(&path, payload.as_bytes())
+}
@@ -1,11 +1,33 @@
+mod agents;
+mod archive;
 mod backup;
+mod blobs;
+mod category;
+mod cli;
+mod compare;
+mod diff;
+mod encryption;
 mod files;
+mod include;
+mod journal;
+mod ledger;
+mod managed;
 mod mapping;
+mod mcp;
 mod paths;
+mod pull;
+mod retention;
+mod settings;
+mod snapshot;
 mod sync;
+mod template;
+mod transform;
 mod types;
+mod verify;
 mod workspace;
 
+use std::collections::HashMap;
+
 use files::{normalize_relative_path, read_text, to_slash_path, write_atomic_bytes};
 use types::*;
 use workspace::{ensure_scope_dir, ensure_workspace_layout};
@@ -63,8 +85,39 @@ fn preview_sync() -> Result<SyncPreview, String> {
 fn apply_sync(
     selected_ids: Vec<String>,
     source_prompt_snapshots: Option<Vec<SourcePromptSnapshot>>,
+    conflict_resolutions: Option<HashMap<String, ConflictResolution>>,
+    expected_hashes: Option<HashMap<String, SyncItemHashes>>,
+) -> Result<ApplySyncResult, String> {
+    sync::apply_sync_inner(
+        selected_ids,
+        source_prompt_snapshots,
+        conflict_resolutions,
+        expected_hashes,
+    )
+}
+
+#[tauri::command]
+fn get_sync_item_content(id: String) -> Result<SyncItemContent, String> {
+    sync::sync_item_content_inner(&id)
+}
+
+#[tauri::command]
+fn preview_pull(agent: Option<String>, category: Option<String>) -> Result<PullPreview, String> {
+    pull::preview_pull_inner(agent, category)
+}
+
+#[tauri::command]
+fn apply_pull(
+    agent: Option<String>,
+    category: Option<String>,
+    selected_ids: Vec<String>,
 ) -> Result<ApplySyncResult, String> {
-    sync::apply_sync_inner(selected_ids, source_prompt_snapshots)
+    pull::apply_pull_inner(agent, category, selected_ids)
+}
+
+#[tauri::command]
+fn get_pull_item_content(id: String) -> Result<SyncItemContent, String> {
+    pull::pull_item_content_inner(&id)
 }
 
 #[tauri::command]
@@ -73,8 +126,66 @@ fn list_backups() -> Result<Vec<BackupInfo>, String> {
 }
 
 #[tauri::command]
-fn restore_backup(backup_id: String) -> Result<RestoreResult, String> {
-    backup::restore_backup_inner(backup_id)
+fn verify_backup(
+    backup_id: String,
+    passphrase: Option<String>,
+) -> Result<BackupVerification, String> {
+    verify::verify_backup_inner(&backup_id, passphrase.as_deref())
+}
+
+#[tauri::command]
+fn verify_all_backups(passphrase: Option<String>) -> Result<Vec<BackupVerification>, String> {
+    verify::verify_all_backups_inner(passphrase.as_deref())
+}
+
+#[tauri::command]
+fn search_backups(query: BackupQuery) -> Result<Vec<BackupInfo>, String> {
+    snapshot::search_backups_inner(&query)
+}
+
+#[tauri::command]
+fn create_snapshot(
+    label: Option<String>,
+    note: Option<String>,
+    tags: Option<Vec<String>>,
+) -> Result<BackupInfo, String> {
+    snapshot::create_snapshot_inner(label, note, tags.unwrap_or_default())
+}
+
+#[tauri::command]
+fn annotate_backup(
+    backup_id: String,
+    label: Option<String>,
+    note: Option<String>,
+    tags: Option<Vec<String>>,
+) -> Result<BackupInfo, String> {
+    snapshot::annotate_backup_inner(&backup_id, label, note, tags.unwrap_or_default())
+}
+
+#[tauri::command]
+fn restore_backup(
+    backup_id: String,
+    filter: Option<RestoreFilter>,
+    passphrase: Option<String>,
+) -> Result<RestoreResult, String> {
+    backup::restore_backup_inner(
+        backup_id,
+        &filter.unwrap_or_default(),
+        passphrase.as_deref(),
+    )
+}
+
+#[tauri::command]
+fn preview_restore(
+    backup_id: String,
+    filter: Option<RestoreFilter>,
+    passphrase: Option<String>,
+) -> Result<RestorePreview, String> {
+    backup::preview_restore_inner(
+        backup_id,
+        &filter.unwrap_or_default(),
+        passphrase.as_deref(),
+    )
 }
 
 #[tauri::command]
@@ -83,8 +194,121 @@ fn delete_backup(backup_id: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-fn get_backup_detail(backup_id: String) -> Result<BackupDetail, String> {
-    backup::get_backup_detail_inner(backup_id)
+fn get_backup_detail(
+    backup_id: String,
+    passphrase: Option<String>,
+) -> Result<BackupDetail, String> {
+    backup::get_backup_detail_inner(backup_id, passphrase.as_deref())
+}
+
+#[tauri::command]
+fn diff_backups(
+    from: String,
+    to: Option<String>,
+    passphrase: Option<String>,
+) -> Result<BackupDiff, String> {
+    compare::diff_backups_inner(&from, to.as_deref(), passphrase.as_deref())
+}
+
+#[tauri::command]
+fn export_backup(backup_id: String, destination: String) -> Result<String, String> {
+    archive::export_backup_inner(&backup_id, std::path::Path::new(&destination))
+}
+
+#[tauri::command]
+fn enable_backup_encryption(passphrase: String) -> Result<(), String> {
+    encryption::enable_backup_encryption_inner(&passphrase)
+}
+
+#[tauri::command]
+fn disable_backup_encryption(passphrase: String) -> Result<(), String> {
+    encryption::disable_backup_encryption_inner(&passphrase)
+}
+
+#[tauri::command]
+fn import_backup(archive: String, passphrase: Option<String>) -> Result<BackupInfo, String> {
+    archive::import_backup_inner(std::path::Path::new(&archive), passphrase.as_deref())
+}
+
+#[tauri::command]
+fn pin_backup(backup_id: String, pinned: bool) -> Result<(), String> {
+    retention::set_backup_pinned_inner(&backup_id, pinned)
+}
+
+#[tauri::command]
+fn prune_backups(dry_run: Option<bool>) -> Result<PruneResult, String> {
+    retention::prune_backups_inner(dry_run.unwrap_or(false))
+}
+
+#[tauri::command]
+fn get_settings() -> Result<settings::Settings, String> {
+    settings::load_settings()
+}
+
+#[tauri::command]
+fn save_settings(settings: settings::Settings) -> Result<settings::Settings, String> {
+    settings::save_settings_inner(&settings)?;
+    settings::load_settings()
+}
+
+#[tauri::command]
+fn get_retention_policy() -> Result<retention::RetentionPolicy, String> {
+    retention::load_retention_policy()
+}
+
+#[tauri::command]
+fn save_retention_policy(
+    policy: retention::RetentionPolicy,
+) -> Result<retention::RetentionPolicy, String> {
+    retention::save_retention_policy_inner(&policy)?;
+    retention::load_retention_policy()
+}
+
+#[tauri::command]
+fn get_mapping() -> Result<mapping::MappingConfig, String> {
+    mapping::load_mapping()
+}
+
+#[tauri::command]
+fn save_mapping(mapping: mapping::MappingConfig) -> Result<mapping::MappingConfig, String> {
+    mapping::save_mapping_inner(&mapping)?;
+    mapping::load_mapping()
+}
+
+#[tauri::command]
+fn reset_category_mapping(category: String) -> Result<mapping::MappingConfig, String> {
+    mapping::reset_category_mapping_inner(&category)
+}
+
+#[tauri::command]
+fn validate_mapping(mapping: mapping::MappingConfig) -> Result<MappingValidation, String> {
+    mapping::validate_mapping_inner(mapping)
+}
+
+#[tauri::command]
+fn add_category(
+    category: String,
+    sync_mode: Option<mapping::SyncMode>,
+) -> Result<mapping::MappingConfig, String> {
+    category::add_category_inner(&category, sync_mode)
+}
+
+#[tauri::command]
+fn rename_category(from: String, to: String) -> Result<mapping::MappingConfig, String> {
+    category::rename_category_inner(&from, &to)
+}
+
+#[tauri::command]
+fn remove_category(
+    category: String,
+    prune: Option<bool>,
+) -> Result<mapping::MappingConfig, String> {
+    category::remove_category_inner(&category, prune.unwrap_or(false))
+}
+
+/// Entry point of the headless `agentdock-cli` binary.
+pub fn run_cli() -> std::process::ExitCode {
+    cli::run()
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -98,10 +322,38 @@ pub fn run() {
             save_scope_file,
             preview_sync,
             apply_sync,
+            get_sync_item_content,
+            preview_pull,
+            apply_pull,
+            get_pull_item_content,
             list_backups,
             restore_backup,
+            preview_restore,
             delete_backup,
             get_backup_detail,
+            diff_backups,
+            search_backups,
+            verify_backup,
+            verify_all_backups,
+            create_snapshot,
+            annotate_backup,
+            export_backup,
+            import_backup,
+            enable_backup_encryption,
+            disable_backup_encryption,
+            pin_backup,
+            prune_backups,
+            get_settings,
+            save_settings,
+            get_retention_policy,
+            save_retention_policy,
+            get_mapping,
+            save_mapping,
+            reset_category_mapping,
+            validate_mapping,
+            add_category,
+            rename_category,
+            remove_category,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,474 @@
+use serde_json::{Map, Value};
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+const MARKER_PREFIX: &str = "agentdock";
+
+/// How a managed target keeps AgentDock's content apart from the user's.
+enum ManagedFormat {
+    /// Marker comments around a block, with the given comment delimiters.
+    Block(&'static str, &'static str),
+    /// Keys from source are merged into the existing JSON object.
+    Json,
+    /// Keys from source are merged into the existing TOML document.
+    Toml,
+}
+
+fn managed_format(target: &Path) -> ManagedFormat {
+    let extension = target
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "json" => ManagedFormat::Json,
+        "toml" => ManagedFormat::Toml,
+        "yaml" | "yml" | "sh" | "bash" | "zsh" | "py" | "rb" | "conf" | "ini" | "env" | "rules" => {
+            ManagedFormat::Block("# ", "")
+        }
+        _ => ManagedFormat::Block("<!-- ", " -->"),
+    }
+}
+
+fn markers(name: &str, open: &str, close: &str) -> (String, String) {
+    (
+        format!("{open}{MARKER_PREFIX}:begin {name}{close}"),
+        format!("{open}{MARKER_PREFIX}:end {name}{close}"),
+    )
+}
+
+/// Byte range of the managed block, begin marker through end marker.
+fn find_block(text: &str, begin: &str, end: &str) -> Result<Option<(usize, usize)>, String> {
+    let start = match text.find(begin) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let after_begin = start + begin.len();
+    let end_offset = text[after_begin..]
+        .find(end)
+        .ok_or_else(|| format!("Managed block is missing its end marker: {end}"))?;
+    Ok(Some((start, after_begin + end_offset + end.len())))
+}
+
+fn replace_block(before: &str, content: &str, begin: &str, end: &str) -> Result<String, String> {
+    let block = format!("{begin}\n{}\n{end}", content.trim_end());
+    match find_block(before, begin, end)? {
+        Some((start, stop)) => Ok(format!("{}{block}{}", &before[..start], &before[stop..])),
+        None if before.trim().is_empty() => Ok(format!("{block}\n")),
+        None => Ok(format!("{}\n\n{block}\n", before.trim_end())),
+    }
+}
+
+fn merge_json_value(target: &mut Value, source: &Value) {
+    match (target, source) {
+        (Value::Object(target_map), Value::Object(source_map)) => {
+            for (key, value) in source_map {
+                match target_map.get_mut(key) {
+                    Some(existing) if existing.is_object() && value.is_object() => {
+                        merge_json_value(existing, value)
+                    }
+                    _ => {
+                        target_map.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, source) => *target = source.clone(),
+    }
+}
+
+/// Paths of the leaf keys of `value`, i.e. every key a merge writes. Empty
+/// objects count as leaves.
+fn json_key_paths(value: &Value, prefix: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                prefix.push(key.clone());
+                json_key_paths(value, prefix, paths);
+                prefix.pop();
+            }
+        }
+        _ if !prefix.is_empty() => paths.push(prefix.clone()),
+        _ => {}
+    }
+}
+
+/// Removes the key at `path`, then any object that removal left empty.
+/// Returns whether a key was removed.
+fn remove_json_path(target: &mut Value, path: &[String]) -> bool {
+    let Some(map) = target.as_object_mut() else {
+        return false;
+    };
+    match path {
+        [] => false,
+        [key] => map.remove(key).is_some(),
+        [key, rest @ ..] => {
+            let Some(child) = map.get_mut(key) else {
+                return false;
+            };
+            let removed = remove_json_path(child, rest);
+            if removed && child.as_object().is_some_and(Map::is_empty) {
+                map.remove(key);
+            }
+            removed
+        }
+    }
+}
+
+fn parse_json_target(before: &str) -> Result<Value, String> {
+    let current: Value =
+        serde_json::from_str(before).map_err(|e| format!("Target is not valid JSON: {e}"))?;
+    if !current.is_object() {
+        return Err("Keyed merge requires JSON objects on both sides".to_string());
+    }
+    Ok(current)
+}
+
+fn write_json(before: &str, current: &Value, merged: &Value) -> Result<String, String> {
+    if merged == current {
+        return Ok(before.to_string());
+    }
+    let payload = serde_json::to_string_pretty(merged).map_err(|e| e.to_string())?;
+    Ok(format!("{payload}\n"))
+}
+
+fn merge_json(
+    before: &str,
+    content: &str,
+    previous: &[Vec<String>],
+) -> Result<(String, Vec<Vec<String>>), String> {
+    let source: Value =
+        serde_json::from_str(content).map_err(|e| format!("Source is not valid JSON: {e}"))?;
+    if !source.is_object() {
+        return Err("Keyed merge requires JSON objects on both sides".to_string());
+    }
+    let mut keys = Vec::new();
+    json_key_paths(&source, &mut Vec::new(), &mut keys);
+    if before.trim().is_empty() {
+        return Ok((content.to_string(), keys));
+    }
+    let current = parse_json_target(before)?;
+
+    let mut merged = current.clone();
+    for path in previous.iter().filter(|path| !keys.contains(path)) {
+        remove_json_path(&mut merged, path);
+    }
+    merge_json_value(&mut merged, &source);
+    Ok((write_json(before, &current, &merged)?, keys))
+}
+
+fn strip_json(before: &str, previous: &[Vec<String>]) -> Result<String, String> {
+    let current = parse_json_target(before)?;
+    let mut stripped = current.clone();
+    for path in previous {
+        remove_json_path(&mut stripped, path);
+    }
+    write_json(before, &current, &stripped)
+}
+
+/// Equal values are left alone so the user's comments and layout survive.
+fn same_toml_item(current: &Item, incoming: &Item) -> bool {
+    match (current.as_value(), incoming.as_value()) {
+        (Some(current), Some(incoming)) => {
+            let (mut current, mut incoming) = (current.clone(), incoming.clone());
+            current.decor_mut().clear();
+            incoming.decor_mut().clear();
+            current.to_string() == incoming.to_string()
+        }
+        _ => current.to_string().trim() == incoming.to_string().trim(),
+    }
+}
+
+pub fn merge_toml_table(target: &mut dyn TableLike, source: &dyn TableLike) {
+    for (key, item) in source.iter() {
+        match target.get_mut(key) {
+            Some(existing) if existing.is_table_like() && item.is_table_like() => {
+                if let (Some(existing), Some(incoming)) =
+                    (existing.as_table_like_mut(), item.as_table_like())
+                {
+                    merge_toml_table(existing, incoming);
+                }
+            }
+            Some(existing) if same_toml_item(existing, item) => {}
+            _ => {
+                target.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+/// Paths of the leaf keys of `table`; empty tables count as leaves.
+fn toml_key_paths(table: &dyn TableLike, prefix: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+    for (key, item) in table.iter() {
+        prefix.push(key.to_string());
+        match item.as_table_like() {
+            Some(child) if !child.is_empty() => toml_key_paths(child, prefix, paths),
+            _ => paths.push(prefix.clone()),
+        }
+        prefix.pop();
+    }
+}
+
+/// Removes the key at `path`, then any table that removal left empty.
+/// Returns whether a key was removed.
+fn remove_toml_path(table: &mut dyn TableLike, path: &[String]) -> bool {
+    match path {
+        [] => false,
+        [key] => table.remove(key).is_some(),
+        [key, rest @ ..] => {
+            let Some(child) = table.get_mut(key).and_then(Item::as_table_like_mut) else {
+                return false;
+            };
+            let removed = remove_toml_path(child, rest);
+            if removed && child.is_empty() {
+                table.remove(key);
+            }
+            removed
+        }
+    }
+}
+
+fn parse_toml_target(before: &str) -> Result<DocumentMut, String> {
+    before
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Target is not valid TOML: {e}"))
+}
+
+fn merge_toml(
+    before: &str,
+    content: &str,
+    previous: &[Vec<String>],
+) -> Result<(String, Vec<Vec<String>>), String> {
+    let source = content
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Source is not valid TOML: {e}"))?;
+    let mut keys = Vec::new();
+    toml_key_paths(source.as_table(), &mut Vec::new(), &mut keys);
+    if before.trim().is_empty() {
+        return Ok((content.to_string(), keys));
+    }
+    let mut merged = parse_toml_target(before)?;
+    for path in previous.iter().filter(|path| !keys.contains(path)) {
+        remove_toml_path(merged.as_table_mut(), path);
+    }
+    merge_toml_table(merged.as_table_mut(), source.as_table());
+    Ok((merged.to_string(), keys))
+}
+
+fn strip_toml(before: &str, previous: &[Vec<String>]) -> Result<String, String> {
+    let mut stripped = parse_toml_target(before)?;
+    let mut removed = false;
+    for path in previous {
+        removed |= remove_toml_path(stripped.as_table_mut(), path);
+    }
+    Ok(if removed {
+        stripped.to_string()
+    } else {
+        before.to_string()
+    })
+}
+
+/// Renders `content` into `before` so that only AgentDock's part of the file
+/// changes: a marker-delimited block for text files, a keyed merge for JSON
+/// and TOML. `previous` lists the keys the last sync merged in, so keys that
+/// left the source are dropped. Returns the file and the keys it now merges
+/// in (none for marker blocks).
+pub fn render_managed(
+    before: &str,
+    content: &str,
+    name: &str,
+    target: &Path,
+    previous: &[Vec<String>],
+) -> Result<(String, Vec<Vec<String>>), String> {
+    match managed_format(target) {
+        ManagedFormat::Block(open, close) => {
+            let (begin, end) = markers(name, open, close);
+            Ok((replace_block(before, content, &begin, &end)?, Vec::new()))
+        }
+        ManagedFormat::Json => merge_json(before, content, previous),
+        ManagedFormat::Toml => merge_toml(before, content, previous),
+    }
+}
+
+/// Removes AgentDock's part from a managed target whose source is gone: the
+/// marker block, or the `previous` keys of a keyed merge.
+pub fn strip_managed(
+    text: &str,
+    name: &str,
+    target: &Path,
+    previous: &[Vec<String>],
+) -> Result<String, String> {
+    let (open, close) = match managed_format(target) {
+        ManagedFormat::Block(open, close) => (open, close),
+        ManagedFormat::Json => return strip_json(text, previous),
+        ManagedFormat::Toml => return strip_toml(text, previous),
+    };
+    let (begin, end) = markers(name, open, close);
+    let Some((start, stop)) = find_block(text, &begin, &end)? else {
+        return Ok(text.to_string());
+    };
+    let head = text[..start].trim_end();
+    let tail = text[stop..].trim_start_matches(['\r', '\n']);
+    Ok(match (head.is_empty(), tail.is_empty()) {
+        (true, _) => tail.to_string(),
+        (false, true) => format!("{head}\n"),
+        (false, false) => format!("{head}\n\n{tail}"),
+    })
+}
+
+/// Extracts AgentDock's block from a managed text target. Keyed-merge formats
+/// cannot be separated from the user's keys and return an error.
+pub fn extract_managed(text: &str, name: &str, target: &Path) -> Result<Option<String>, String> {
+    match managed_format(target) {
+        ManagedFormat::Block(open, close) => {
+            let (begin, end) = markers(name, open, close);
+            Ok(find_block(text, &begin, &end)?.map(|(start, stop)| {
+                let inner = &text[start + begin.len()..stop - end.len()];
+                format!("{}\n", inner.trim_matches(['\r', '\n']))
+            }))
+        }
+        ManagedFormat::Json | ManagedFormat::Toml => {
+            Err("Keyed-merge targets cannot be separated from user keys".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(paths: &[&[&str]]) -> Vec<Vec<String>> {
+        paths
+            .iter()
+            .map(|path| path.iter().map(|key| key.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn replaces_marker_blocks_in_place() {
+        let target = Path::new("AGENTS.md");
+        let (first, merged) =
+            render_managed("# Mine\n", "rules v1\n", "rules", target, &[]).unwrap();
+        assert!(merged.is_empty());
+        assert_eq!(
+            first,
+            "# Mine\n\n<!-- agentdock:begin rules -->\nrules v1\n<!-- agentdock:end rules -->\n"
+        );
+        let edited = first.replace("# Mine\n", "# Mine, edited\n") + "tail\n";
+        let (second, _) = render_managed(&edited, "rules v2", "rules", target, &[]).unwrap();
+        assert_eq!(
+            second,
+            "# Mine, edited\n\n<!-- agentdock:begin rules -->\nrules v2\n<!-- agentdock:end rules -->\ntail\n"
+        );
+        assert_eq!(
+            extract_managed(&second, "rules", target)
+                .unwrap()
+                .as_deref(),
+            Some("rules v2\n")
+        );
+    }
+
+    #[test]
+    fn uses_hash_markers_for_scripts() {
+        let (text, _) = render_managed("", "export A=1\n", "env", Path::new("rc.sh"), &[]).unwrap();
+        assert_eq!(
+            text,
+            "# agentdock:begin env\nexport A=1\n# agentdock:end env\n"
+        );
+    }
+
+    #[test]
+    fn strips_marker_blocks() {
+        let target = Path::new("AGENTS.md");
+        let block = "<!-- agentdock:begin rules -->\nrules\n<!-- agentdock:end rules -->";
+        let strip = |text: &str| strip_managed(text, "rules", target, &[]).unwrap();
+        assert_eq!(
+            strip(&format!("head\n\n{block}\n\ntail\n")),
+            "head\n\ntail\n"
+        );
+        assert_eq!(strip(&format!("head\n\n{block}\n")), "head\n");
+        assert_eq!(strip(&format!("{block}\n")), "");
+        assert_eq!(strip("no block\n"), "no block\n");
+        assert!(
+            strip_managed("<!-- agentdock:begin rules -->\nopen", "rules", target, &[])
+                .unwrap_err()
+                .contains("missing its end marker")
+        );
+    }
+
+    #[test]
+    fn merges_json_and_drops_keys_that_left_the_source() {
+        let target = Path::new("settings.json");
+        let before = r#"{"theme": "dark", "tools": {"a": 1, "b": 2}}"#;
+        let (merged, merged_keys) = render_managed(
+            before,
+            r#"{"tools": {"a": 3, "c": 4}}"#,
+            "settings",
+            target,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(merged_keys, keys(&[&["tools", "a"], &["tools", "c"]]));
+        let value: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"theme": "dark", "tools": {"a": 3, "b": 2, "c": 4}})
+        );
+
+        let (merged, merged_keys) = render_managed(
+            &merged,
+            r#"{"tools": {"a": 3}}"#,
+            "settings",
+            target,
+            &merged_keys,
+        )
+        .unwrap();
+        assert_eq!(merged_keys, keys(&[&["tools", "a"]]));
+        let value: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"theme": "dark", "tools": {"a": 3, "b": 2}})
+        );
+
+        let stripped = strip_managed(&merged, "settings", target, &merged_keys).unwrap();
+        let value: Value = serde_json::from_str(&stripped).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"theme": "dark", "tools": {"b": 2}})
+        );
+        assert!(render_managed("[1]", "{}", "settings", target, &[]).is_err());
+    }
+
+    #[test]
+    fn merges_toml_and_keeps_unchanged_values_as_written() {
+        let target = Path::new("config.toml");
+        let before = "# mine\nmodel = \"o3\"  # keep\n\n[mcp]\nuser = true\n";
+        let (merged, merged_keys) = render_managed(
+            before,
+            "model = \"o3\"\n[mcp]\nshared = 1\n",
+            "config",
+            target,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(merged_keys, keys(&[&["model"], &["mcp", "shared"]]));
+        assert!(merged.contains("model = \"o3\"  # keep"));
+        assert!(merged.contains("user = true") && merged.contains("shared = 1"));
+
+        let (merged, merged_keys) = render_managed(
+            &merged,
+            "[mcp]\nshared = 1\n",
+            "config",
+            target,
+            &merged_keys,
+        )
+        .unwrap();
+        assert!(!merged.contains("model"));
+        let stripped = strip_managed(&merged, "config", target, &merged_keys).unwrap();
+        assert!(stripped.contains("user = true") && !stripped.contains("shared"));
+        assert_eq!(
+            strip_managed(before, "config", target, &[]).unwrap(),
+            before
+        );
+    }
+}
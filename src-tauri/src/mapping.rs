@@ -1,27 +1,50 @@
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Component, Path};
 
+use crate::agents::{load_agent_registry, AgentRegistry, LEGACY_MCP_TARGETS};
 use crate::files::write_atomic_bytes;
+use crate::ledger::Ownership;
 use crate::paths::{mapping_path, source_root};
+use crate::sync::build_sync_items;
+use crate::types::{MappingFieldError, MappingFileChange, MappingTargetChange, MappingValidation};
 
+/// Built-in categories, always present. Users may add their own on top.
 pub const CATEGORY_NAMES: [&str; 5] = ["instructions", "skills", "plugins", "commands", "mcp"];
+pub const MAPPING_VERSION: u32 = 4;
+const LEGACY_MAPPING_VERSION: u32 = 1;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum SyncMode {
     #[default]
     Replace,
     Append,
+    /// Keep AgentDock's content in a marker-delimited block (or keyed merge for
+    /// JSON/TOML) and leave the rest of the target untouched.
+    Managed,
+    /// Render the canonical MCP servers into the agent's native config and
+    /// merge them in by server name.
+    Mcp,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CategoryMapping {
-    pub codex: String,
-    pub gemini: String,
-    pub claude: String,
+    /// Target path per agent id, relative to that agent's root.
+    pub targets: BTreeMap<String, String>,
     #[serde(default)]
     pub sync_mode: SyncMode,
+    /// Render `{{…}}` template tags and expand `@include` lines in the
+    /// category's source files. Only `instructions` opts in by default, so
+    /// skills or commands that contain literal `{{` sync untouched.
+    #[serde(default)]
+    pub templates: bool,
+}
+
+impl CategoryMapping {
+    pub fn target_for(&self, agent: &str) -> Option<&str> {
+        self.targets.get(agent).map(String::as_str)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -30,88 +53,190 @@ pub struct MappingConfig {
     pub categories: BTreeMap<String, CategoryMapping>,
 }
 
-pub fn default_mapping() -> MappingConfig {
+/// Version 1 mapping shape with one fixed field per agent
+/// (`{"codex": "...", "gemini": "...", "claude": "...", "sync_mode": "..."}`).
+#[derive(Debug, Deserialize)]
+struct LegacyCategoryMapping {
+    #[serde(default)]
+    sync_mode: SyncMode,
+    #[serde(flatten)]
+    targets: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyMappingConfig {
+    categories: BTreeMap<String, LegacyCategoryMapping>,
+}
+
+/// Keeps the legacy version so `normalize_mapping` runs every upgrade since.
+fn migrate_legacy_mapping(raw: &str) -> Option<MappingConfig> {
+    let legacy = serde_json::from_str::<LegacyMappingConfig>(raw).ok()?;
+    let categories = legacy
+        .categories
+        .into_iter()
+        .map(|(category, mapping)| {
+            let templates = category == "instructions";
+            (
+                category,
+                CategoryMapping {
+                    targets: mapping.targets,
+                    sync_mode: mapping.sync_mode,
+                    templates,
+                },
+            )
+        })
+        .collect();
+    Some(MappingConfig {
+        version: LEGACY_MAPPING_VERSION,
+        categories,
+    })
+}
+
+/// Category names in display order: built-ins first, then custom ones.
+pub fn category_names(mapping: &MappingConfig) -> Vec<String> {
+    let custom = mapping
+        .categories
+        .keys()
+        .filter(|category| !CATEGORY_NAMES.contains(&category.as_str()));
+    CATEGORY_NAMES
+        .iter()
+        .map(|category| category.to_string())
+        .chain(custom.cloned())
+        .collect()
+}
+
+/// Category names double as source folder names, so custom ones are kept to
+/// a portable subset.
+pub fn validate_category_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!(
+            "Category name may only use lowercase letters, digits, '-' and '_': {name}"
+        ));
+    }
+    Ok(())
+}
+
+pub fn default_mapping(registry: &AgentRegistry) -> MappingConfig {
     let mut categories = BTreeMap::new();
     for category in CATEGORY_NAMES {
-        categories.insert(category.to_string(), default_category_mapping(category));
+        categories.insert(
+            category.to_string(),
+            default_category_mapping(category, registry),
+        );
     }
     MappingConfig {
-        version: 1,
+        version: MAPPING_VERSION,
         categories,
     }
 }
 
-pub fn default_category_mapping(category: &str) -> CategoryMapping {
-    match category {
-        "instructions" => CategoryMapping {
-            codex: "AGENTS.md".to_string(),
-            gemini: "GEMINI.md".to_string(),
-            claude: "CLAUDE.md".to_string(),
-            sync_mode: SyncMode::Replace,
-        },
-        "skills" => CategoryMapping {
-            codex: "skills".to_string(),
-            gemini: "skills".to_string(),
-            claude: "skills".to_string(),
-            sync_mode: SyncMode::Replace,
-        },
-        "plugins" => CategoryMapping {
-            codex: "plugins".to_string(),
-            gemini: "plugins".to_string(),
-            claude: "plugins".to_string(),
-            sync_mode: SyncMode::Replace,
-        },
-        "commands" => CategoryMapping {
-            codex: "rules".to_string(),
-            gemini: "commands".to_string(),
-            claude: "commands".to_string(),
-            sync_mode: SyncMode::Replace,
-        },
-        "mcp" => CategoryMapping {
-            codex: "mcp.json".to_string(),
-            gemini: "antigravity/mcp_config.json".to_string(),
-            claude: "mcp.json".to_string(),
-            sync_mode: SyncMode::Replace,
-        },
-        _ => CategoryMapping {
-            codex: category.to_string(),
-            gemini: category.to_string(),
-            claude: category.to_string(),
-            sync_mode: SyncMode::Replace,
+pub fn default_category_mapping(category: &str, registry: &AgentRegistry) -> CategoryMapping {
+    CategoryMapping {
+        targets: registry
+            .agents
+            .iter()
+            .map(|agent| (agent.id.clone(), agent.default_target(category)))
+            .collect(),
+        sync_mode: if category == "mcp" {
+            SyncMode::Mcp
+        } else {
+            SyncMode::Replace
         },
+        templates: category == "instructions",
+    }
+}
+
+/// Early releases mapped `commands` and `mcp` to a directory of the same name
+/// for every agent; reset those to the registry defaults, keeping the mode.
+fn reset_legacy_category(
+    mapping: &mut MappingConfig,
+    category: &str,
+    legacy_target: &str,
+    registry: &AgentRegistry,
+) -> bool {
+    let Some(current) = mapping.categories.get_mut(category) else {
+        return false;
+    };
+    if current.targets.is_empty() || current.targets.values().any(|t| t != legacy_target) {
+        return false;
+    }
+    let mut defaults = default_category_mapping(category, registry);
+    if defaults.targets == current.targets {
+        return false;
+    }
+    defaults.sync_mode = current.sync_mode.clone();
+    defaults.templates = current.templates;
+    *current = defaults;
+    true
+}
+
+/// Before version 3 `mcp` was copied verbatim; move untouched mappings over to
+/// the structured merge into each agent's native config.
+fn upgrade_mcp_category(mapping: &mut MappingConfig, registry: &AgentRegistry) -> bool {
+    let Some(current) = mapping.categories.get_mut("mcp") else {
+        return false;
+    };
+    if !matches!(current.sync_mode, SyncMode::Replace) {
+        return false;
     }
+    let defaults = default_category_mapping("mcp", registry);
+    let untouched = current.targets.iter().all(|(agent, target)| {
+        defaults.targets.get(agent) == Some(target)
+            || LEGACY_MCP_TARGETS.contains(&(agent.as_str(), target.as_str()))
+    });
+    if !untouched {
+        return false;
+    }
+    *current = defaults;
+    true
 }
 
-pub fn normalize_mapping(mut mapping: MappingConfig) -> (MappingConfig, bool) {
+pub fn normalize_mapping(
+    mut mapping: MappingConfig,
+    registry: &AgentRegistry,
+) -> (MappingConfig, bool) {
     let mut changed = false;
+    let loaded_version = mapping.version;
+
+    if mapping.version < MAPPING_VERSION {
+        mapping.version = MAPPING_VERSION;
+        changed = true;
+    }
 
     for category in CATEGORY_NAMES {
         if !mapping.categories.contains_key(category) {
-            mapping
-                .categories
-                .insert(category.to_string(), default_category_mapping(category));
+            mapping.categories.insert(
+                category.to_string(),
+                default_category_mapping(category, registry),
+            );
             changed = true;
         }
     }
 
-    if let Some(commands) = mapping.categories.get_mut("commands") {
-        if commands.codex == "commands"
-            && commands.gemini == "commands"
-            && commands.claude == "commands"
-        {
-            let saved_mode = commands.sync_mode.clone();
-            *commands = default_category_mapping("commands");
-            commands.sync_mode = saved_mode;
-            changed = true;
+    changed |= reset_legacy_category(&mut mapping, "commands", "commands", registry);
+    changed |= reset_legacy_category(&mut mapping, "mcp", "mcp", registry);
+    if loaded_version < 3 {
+        changed |= upgrade_mcp_category(&mut mapping, registry);
+    }
+    // Before version 4 every category was templated; keep that for prompts.
+    if loaded_version < 4 {
+        if let Some(instructions) = mapping.categories.get_mut("instructions") {
+            instructions.templates = true;
         }
     }
 
-    if let Some(mcp) = mapping.categories.get_mut("mcp") {
-        if mcp.codex == "mcp" && mcp.gemini == "mcp" && mcp.claude == "mcp" {
-            let saved_mode = mcp.sync_mode.clone();
-            *mcp = default_category_mapping("mcp");
-            mcp.sync_mode = saved_mode;
-            changed = true;
+    for (category, target) in mapping.categories.iter_mut() {
+        for agent in &registry.agents {
+            if !target.targets.contains_key(&agent.id) {
+                target
+                    .targets
+                    .insert(agent.id.clone(), agent.default_target(category));
+                changed = true;
+            }
         }
     }
 
@@ -142,52 +267,222 @@ fn validate_subdir_path(path: &str) -> Result<(), String> {
     Ok(())
 }
 
-fn validate_mapping(mapping: &MappingConfig) -> Result<(), String> {
+fn field_error(
+    category: Option<&str>,
+    agent: Option<&str>,
+    field: &str,
+    message: String,
+) -> MappingFieldError {
+    MappingFieldError {
+        category: category.map(str::to_string),
+        agent: agent.map(str::to_string),
+        field: field.to_string(),
+        message,
+    }
+}
+
+/// Every problem in `mapping`, attributed to the field it was found in.
+pub fn mapping_field_errors(mapping: &MappingConfig) -> Vec<MappingFieldError> {
+    let mut errors = Vec::new();
     if mapping.version == 0 {
-        return Err("Mapping version must be greater than 0".to_string());
+        errors.push(field_error(
+            None,
+            None,
+            "version",
+            "Mapping version must be greater than 0".to_string(),
+        ));
     }
     for (category, target) in &mapping.categories {
         if category.trim().is_empty() {
-            return Err("Category name cannot be empty".to_string());
+            errors.push(field_error(
+                Some(category),
+                None,
+                "category",
+                "Category name cannot be empty".to_string(),
+            ));
+        } else if !matches!(
+            Path::new(category)
+                .components()
+                .collect::<Vec<_>>()
+                .as_slice(),
+            [Component::Normal(_)]
+        ) {
+            errors.push(field_error(
+                Some(category),
+                None,
+                "category",
+                format!("Category name must be a single folder name: {category}"),
+            ));
+        }
+        for (agent, path) in &target.targets {
+            if let Err(message) = validate_subdir_path(path) {
+                errors.push(field_error(Some(category), Some(agent), "target", message));
+            }
         }
-        validate_subdir_path(&target.codex)?;
-        validate_subdir_path(&target.gemini)?;
-        validate_subdir_path(&target.claude)?;
     }
-    Ok(())
+    errors
+}
+
+fn validate_mapping(mapping: &MappingConfig) -> Result<(), String> {
+    match mapping_field_errors(mapping).into_iter().next() {
+        Some(error) => Err(error.message),
+        None => Ok(()),
+    }
+}
+
+/// Per-agent targets whose path, sync mode or templating differ between two
+/// mappings.
+fn mapping_changes(current: &MappingConfig, proposed: &MappingConfig) -> Vec<MappingTargetChange> {
+    let mut categories: Vec<&String> = current
+        .categories
+        .keys()
+        .chain(proposed.categories.keys())
+        .collect();
+    categories.sort();
+    categories.dedup();
+
+    let mut changes = Vec::new();
+    for category in categories {
+        let before = current.categories.get(category);
+        let after = proposed.categories.get(category);
+        let mut agents: Vec<&String> = before
+            .into_iter()
+            .chain(after)
+            .flat_map(|mapping| mapping.targets.keys())
+            .collect();
+        agents.sort();
+        agents.dedup();
+
+        for agent in agents {
+            let change = MappingTargetChange {
+                category: category.clone(),
+                agent: agent.clone(),
+                before_target: before.and_then(|m| m.target_for(agent)).map(str::to_string),
+                after_target: after.and_then(|m| m.target_for(agent)).map(str::to_string),
+                before_sync_mode: before.map(|m| m.sync_mode.clone()),
+                after_sync_mode: after.map(|m| m.sync_mode.clone()),
+                before_templates: before.map(|m| m.templates),
+                after_templates: after.map(|m| m.templates),
+            };
+            if change.before_target != change.after_target
+                || change.before_sync_mode != change.after_sync_mode
+                || change.before_templates != change.after_templates
+            {
+                changes.push(change);
+            }
+        }
+    }
+    changes
+}
+
+/// Target files the next sync would change under `proposed` that it would
+/// leave alone, or change differently, under `current`.
+fn mapping_file_changes(
+    current: &MappingConfig,
+    proposed: &MappingConfig,
+    registry: &AgentRegistry,
+) -> Result<Vec<MappingFileChange>, String> {
+    let planned: HashMap<(String, String), (String, String)> = build_sync_items(current, registry)?
+        .into_iter()
+        .map(|item| {
+            (
+                (item.agent, item.target_relative_path),
+                (item.status, item.after_hash),
+            )
+        })
+        .collect();
+    Ok(build_sync_items(proposed, registry)?
+        .into_iter()
+        .filter(|item| item.status != "unchanged")
+        .filter(|item| {
+            let key = (item.agent.clone(), item.target_relative_path.clone());
+            planned.get(&key) != Some(&(item.status.clone(), item.after_hash.clone()))
+        })
+        .map(|item| MappingFileChange {
+            orphaned: matches!(item.ownership, Ownership::Released),
+            agent: item.agent,
+            category: item.category,
+            target_relative_path: item.target_relative_path,
+            status: item.status,
+        })
+        .collect())
+}
+
+/// Checks a proposed mapping without saving it and lists the targets and
+/// files saving it would change.
+pub fn validate_mapping_inner(mapping: MappingConfig) -> Result<MappingValidation, String> {
+    let registry = load_agent_registry()?;
+    let current = load_mapping()?;
+    let (proposed, _) = normalize_mapping(mapping, &registry);
+    let errors = mapping_field_errors(&proposed);
+    let files = if errors.is_empty() {
+        mapping_file_changes(&current, &proposed, &registry)?
+    } else {
+        Vec::new()
+    };
+    Ok(MappingValidation {
+        valid: errors.is_empty(),
+        errors,
+        changes: mapping_changes(&current, &proposed),
+        files,
+    })
+}
+
+/// Puts one category back to the registry's default targets and sync mode.
+pub fn reset_category_mapping_inner(category: &str) -> Result<MappingConfig, String> {
+    let mut mapping = load_mapping()?;
+    if !mapping.categories.contains_key(category) {
+        return Err(format!("Unknown category: {category}"));
+    }
+    let registry = load_agent_registry()?;
+    mapping.categories.insert(
+        category.to_string(),
+        default_category_mapping(category, &registry),
+    );
+    save_mapping_inner(&mapping)?;
+    load_mapping()
 }
 
 pub fn save_mapping_inner(mapping: &MappingConfig) -> Result<(), String> {
-    let (normalized, _) = normalize_mapping(mapping.clone());
+    let registry = load_agent_registry()?;
+    let (normalized, _) = normalize_mapping(mapping.clone(), &registry);
     validate_mapping(&normalized)?;
     let path = mapping_path()?;
     let payload = serde_json::to_string_pretty(&normalized).map_err(|e| e.to_string())?;
     write_atomic_bytes(&path, payload.as_bytes())
 }
 
+/// Reads `mapping.json` in the current shape or the legacy version 1 one.
+fn parse_mapping(raw: &str) -> Option<MappingConfig> {
+    serde_json::from_str::<MappingConfig>(raw)
+        .ok()
+        .or_else(|| migrate_legacy_mapping(raw))
+}
+
 pub fn load_mapping() -> Result<MappingConfig, String> {
     use crate::workspace::ensure_workspace_layout;
 
     ensure_workspace_layout()?;
+    let registry = load_agent_registry()?;
     let path = mapping_path()?;
     if !path.exists() {
-        let mapping = default_mapping();
+        let mapping = default_mapping(&registry);
         save_mapping_inner(&mapping)?;
         return Ok(mapping);
     }
 
     let raw = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
-    match serde_json::from_str::<MappingConfig>(&raw) {
-        Ok(mapping) => {
-            let (normalized, changed) = normalize_mapping(mapping);
+    match parse_mapping(&raw) {
+        Some(mapping) => {
+            let (normalized, changed) = normalize_mapping(mapping, &registry);
             validate_mapping(&normalized)?;
             if changed {
                 save_mapping_inner(&normalized)?;
             }
             Ok(normalized)
         }
-        Err(_) => {
-            let mapping = default_mapping();
+        None => {
+            let mapping = default_mapping(&registry);
             save_mapping_inner(&mapping)?;
             Ok(mapping)
         }
@@ -195,7 +490,7 @@ pub fn load_mapping() -> Result<MappingConfig, String> {
 }
 
 /// When source directory is empty (first launch), bootstrap by reading
-/// the core prompt files from the three agent CLIs.
+/// the core prompt files from every registered agent.
 pub fn bootstrap_source_from_agents(mapping: &MappingConfig) -> Result<bool, String> {
     let src_root = source_root()?;
 
@@ -212,16 +507,14 @@ pub fn bootstrap_source_from_agents(mapping: &MappingConfig) -> Result<bool, Str
     let category_dir = src_root.join("instructions");
     std::fs::create_dir_all(&category_dir).map_err(|e| e.to_string())?;
 
-    let agents: [(&str, &str); 3] = [
-        ("claude", &instructions.claude),
-        ("codex", &instructions.codex),
-        ("gemini", &instructions.gemini),
-    ];
-
+    let registry = load_agent_registry()?;
     let mut bootstrapped = false;
-    for (agent, mapped_path) in &agents {
-        let agent_root = crate::paths::resolve_agent_root(agent)?;
-        let target_file = agent_root.join(mapped_path);
+    for agent in &registry.agents {
+        let mapped_path = match instructions.target_for(&agent.id) {
+            Some(path) if looks_like_file_mapping_path(path) => path,
+            _ => continue,
+        };
+        let target_file = agent.root_path()?.join(mapped_path);
         if !target_file.exists() {
             continue;
         }
@@ -229,10 +522,36 @@ pub fn bootstrap_source_from_agents(mapping: &MappingConfig) -> Result<bool, Str
         if content.trim().is_empty() {
             continue;
         }
-        let source_name = format!("{agent}.md");
+        let source_name = format!("{}.md", agent.id);
         write_atomic_bytes(&category_dir.join(&source_name), content.as_bytes())?;
         bootstrapped = true;
     }
 
     Ok(bootstrapped)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::default_agent_registry;
+
+    #[test]
+    fn upgrades_legacy_mcp_mapping_to_structured_merge() {
+        let raw = r#"{
+            "version": 1,
+            "categories": {
+                "instructions": {"codex": "AGENTS.md", "gemini": "GEMINI.md", "claude": "CLAUDE.md", "sync_mode": "replace"},
+                "mcp": {"codex": "mcp.json", "gemini": "antigravity/mcp_config.json", "claude": "mcp.json", "sync_mode": "replace"}
+            }
+        }"#;
+        let registry = default_agent_registry();
+        let legacy = parse_mapping(raw).unwrap();
+        assert_eq!(legacy.version, LEGACY_MAPPING_VERSION);
+        let (mapping, changed) = normalize_mapping(legacy, &registry);
+        assert!(changed);
+        assert_eq!(mapping.version, MAPPING_VERSION);
+        assert_eq!(mapping.categories["mcp"].sync_mode, SyncMode::Mcp);
+        assert!(mapping.categories["instructions"].templates);
+        assert!(validate_mapping(&mapping).is_ok());
+    }
+}
@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use toml_edit::{Array, DocumentMut, InlineTable, Item, Table};
+
+use crate::agents::AgentDefinition;
+use crate::managed::merge_toml_table;
+
+/// Native MCP config shape an agent reads.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum McpFormat {
+    /// `[mcp_servers.<name>]` tables in `config.toml`.
+    Codex,
+    /// `mcpServers` in `settings.json`; HTTP servers use `httpUrl`.
+    Gemini,
+    /// `mcpServers` in a JSON config; HTTP servers carry `"type": "http"`.
+    Claude,
+}
+
+/// One server in the canonical `source/mcp` definition.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct McpServer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Only these agents get the server; empty means every agent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_agents: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_agents: Vec<String>,
+    /// Agent-specific keys (`cwd`, `timeout`, ...) passed through as-is.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl McpServer {
+    fn applies_to(&self, agent: &str) -> bool {
+        (self.include_agents.is_empty() || self.include_agents.iter().any(|id| id == agent))
+            && !self.exclude_agents.iter().any(|id| id == agent)
+    }
+
+    /// The server entry as `format` spells it.
+    fn native(&self, format: McpFormat) -> Map<String, Value> {
+        let mut entry = Map::new();
+        if let Some(command) = &self.command {
+            entry.insert("command".to_string(), Value::from(command.as_str()));
+        }
+        if !self.args.is_empty() {
+            entry.insert("args".to_string(), Value::from(self.args.clone()));
+        }
+        if !self.env.is_empty() {
+            entry.insert("env".to_string(), string_map(&self.env));
+        }
+        if let Some(url) = &self.url {
+            let key = match format {
+                McpFormat::Gemini => "httpUrl",
+                McpFormat::Codex | McpFormat::Claude => "url",
+            };
+            if format == McpFormat::Claude {
+                entry.insert("type".to_string(), Value::from("http"));
+            }
+            entry.insert(key.to_string(), Value::from(url.as_str()));
+        }
+        if !self.headers.is_empty() {
+            let key = match format {
+                McpFormat::Codex => "http_headers",
+                McpFormat::Gemini | McpFormat::Claude => "headers",
+            };
+            entry.insert(key.to_string(), string_map(&self.headers));
+        }
+        for (key, value) in &self.extra {
+            if !entry.contains_key(key) {
+                entry.insert(key.clone(), value.clone());
+            }
+        }
+        entry
+    }
+}
+
+fn string_map(map: &BTreeMap<String, String>) -> Value {
+    Value::Object(
+        map.iter()
+            .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+            .collect(),
+    )
+}
+
+/// Parses `source/mcp`. Accepts `{"servers": {...}}` as well as the
+/// `{"mcpServers": {...}}` shape older workspaces copied verbatim.
+pub fn parse_mcp_source(content: &str) -> Result<BTreeMap<String, McpServer>, String> {
+    let root: Value =
+        serde_json::from_str(content).map_err(|e| format!("MCP source is not valid JSON: {e}"))?;
+    let servers = root
+        .get("servers")
+        .or_else(|| root.get("mcpServers"))
+        .cloned()
+        .ok_or_else(|| "MCP source must define a \"servers\" object".to_string())?;
+    serde_json::from_value(servers).map_err(|e| format!("Invalid MCP server definition: {e}"))
+}
+
+fn mcp_format_for(agent: &AgentDefinition, target: &Path) -> McpFormat {
+    if let Some(format) = agent.mcp_format {
+        return format;
+    }
+    match target.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => McpFormat::Codex,
+        _ => McpFormat::Claude,
+    }
+}
+
+fn merge_json_servers(
+    before: &str,
+    servers: &BTreeMap<String, Map<String, Value>>,
+    stale: &[&String],
+) -> Result<String, String> {
+    let current: Value = if before.trim().is_empty() {
+        Value::Object(Map::new())
+    } else {
+        serde_json::from_str(before).map_err(|e| format!("Target is not valid JSON: {e}"))?
+    };
+    let mut merged = current.clone();
+    let root = merged
+        .as_object_mut()
+        .ok_or_else(|| "MCP target must be a JSON object".to_string())?;
+    if servers.is_empty() && !root.contains_key("mcpServers") {
+        return Ok(before.to_string());
+    }
+    let section = root
+        .entry("mcpServers")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| "\"mcpServers\" in the target must be an object".to_string())?;
+
+    for name in stale {
+        section.remove(name.as_str());
+    }
+    for (name, entry) in servers {
+        section.insert(name.clone(), Value::Object(entry.clone()));
+    }
+
+    if merged == current {
+        return Ok(before.to_string());
+    }
+    let payload = serde_json::to_string_pretty(&merged).map_err(|e| e.to_string())?;
+    Ok(format!("{payload}\n"))
+}
+
+fn toml_value(value: &Value) -> Option<toml_edit::Value> {
+    match value {
+        Value::Null => None,
+        Value::Bool(flag) => Some((*flag).into()),
+        Value::Number(number) => number
+            .as_i64()
+            .map(toml_edit::Value::from)
+            .or_else(|| number.as_f64().map(toml_edit::Value::from)),
+        Value::String(text) => Some(text.as_str().into()),
+        Value::Array(values) => Some(toml_edit::Value::Array(
+            values.iter().filter_map(toml_value).collect::<Array>(),
+        )),
+        Value::Object(map) => {
+            let mut table = InlineTable::new();
+            for (key, value) in map {
+                if let Some(value) = toml_value(value) {
+                    table.insert(key, value);
+                }
+            }
+            Some(toml_edit::Value::InlineTable(table))
+        }
+    }
+}
+
+fn merge_toml_servers(
+    before: &str,
+    servers: &BTreeMap<String, Map<String, Value>>,
+    stale: &[&String],
+) -> Result<String, String> {
+    let mut document = before
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Target is not valid TOML: {e}"))?;
+    if servers.is_empty() && !document.contains_key("mcp_servers") {
+        return Ok(before.to_string());
+    }
+    let section = document
+        .entry("mcp_servers")
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_like_mut()
+        .ok_or_else(|| "\"mcp_servers\" in the target must be a table".to_string())?;
+
+    for name in stale {
+        section.remove(name);
+    }
+    for (name, entry) in servers {
+        let mut incoming = Table::new();
+        for (key, value) in entry {
+            if let Some(value) = toml_value(value) {
+                incoming.insert(key, Item::Value(value));
+            }
+        }
+        match section.get_mut(name).and_then(Item::as_table_like_mut) {
+            // Key-level merge keeps the user's comments on values that did not change.
+            Some(existing) => {
+                let dropped: Vec<String> = existing
+                    .iter()
+                    .map(|(key, _)| key.to_string())
+                    .filter(|key| !incoming.contains_key(key))
+                    .collect();
+                for key in dropped {
+                    existing.remove(&key);
+                }
+                merge_toml_table(existing, &incoming);
+            }
+            None => {
+                section.insert(name, Item::Table(incoming));
+            }
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// Drops the servers AgentDock merged into `before` (`previous`), for a config
+/// whose MCP source is gone. Unrelated keys and servers are kept.
+pub fn strip_mcp(
+    before: &str,
+    agent: &AgentDefinition,
+    target: &Path,
+    previous: &[String],
+) -> Result<String, String> {
+    let stale: Vec<&String> = previous.iter().collect();
+    match mcp_format_for(agent, target) {
+        McpFormat::Codex => merge_toml_servers(before, &BTreeMap::new(), &stale),
+        McpFormat::Gemini | McpFormat::Claude => {
+            merge_json_servers(before, &BTreeMap::new(), &stale)
+        }
+    }
+}
+
+/// Renders the canonical servers into `agent`'s native config and merges them
+/// into `before` by server name. Unrelated keys and servers AgentDock never
+/// wrote are kept; `previous` lists the names written by the last sync so
+/// removed or excluded servers can be dropped. Returns the merged file and
+/// the server names it now manages.
+pub fn render_mcp(
+    before: &str,
+    content: &str,
+    agent: &AgentDefinition,
+    target: &Path,
+    previous: &[String],
+) -> Result<(String, Vec<String>), String> {
+    let format = mcp_format_for(agent, target);
+    let servers: BTreeMap<String, Map<String, Value>> = parse_mcp_source(content)?
+        .into_iter()
+        .filter(|(_, server)| server.applies_to(&agent.id))
+        .map(|(name, server)| (name, server.native(format)))
+        .collect();
+    let stale: Vec<&String> = previous
+        .iter()
+        .filter(|name| !servers.contains_key(*name))
+        .collect();
+
+    let rendered = match format {
+        McpFormat::Codex => merge_toml_servers(before, &servers, &stale)?,
+        McpFormat::Gemini | McpFormat::Claude => merge_json_servers(before, &servers, &stale)?,
+    };
+    Ok((rendered, servers.into_keys().collect()))
+}
@@ -4,7 +4,14 @@ use std::path::PathBuf;
 pub const APP_DIR_NAME: &str = ".agentdock";
 pub const SOURCE_DIR_NAME: &str = "source";
 pub const BACKUPS_DIR_NAME: &str = "backups";
+pub const OBJECTS_DIR_NAME: &str = "objects";
 pub const MAPPING_FILE_NAME: &str = "mapping.json";
+pub const AGENTS_FILE_NAME: &str = "agents.json";
+pub const LEDGER_DIR_NAME: &str = "ledger";
+pub const VARS_FILE_NAME: &str = "vars.json";
+pub const RETENTION_FILE_NAME: &str = "retention.json";
+pub const SETTINGS_FILE_NAME: &str = "settings.json";
+pub const APPLY_LOCK_FILE_NAME: &str = "apply.lock";
 
 pub fn home_dir() -> Result<PathBuf, String> {
     env::var_os("HOME")
@@ -24,23 +31,66 @@ pub fn backups_root() -> Result<PathBuf, String> {
     Ok(app_root()?.join(BACKUPS_DIR_NAME))
 }
 
+/// Content-addressed store shared by all backups.
+pub fn objects_root() -> Result<PathBuf, String> {
+    Ok(backups_root()?.join(OBJECTS_DIR_NAME))
+}
+
 pub fn mapping_path() -> Result<PathBuf, String> {
     Ok(app_root()?.join(MAPPING_FILE_NAME))
 }
 
+pub fn ledger_root() -> Result<PathBuf, String> {
+    Ok(app_root()?.join(LEDGER_DIR_NAME))
+}
+
+pub fn agents_path() -> Result<PathBuf, String> {
+    Ok(app_root()?.join(AGENTS_FILE_NAME))
+}
+
+pub fn vars_path() -> Result<PathBuf, String> {
+    Ok(app_root()?.join(VARS_FILE_NAME))
+}
+
+pub fn retention_path() -> Result<PathBuf, String> {
+    Ok(app_root()?.join(RETENTION_FILE_NAME))
+}
+
+pub fn settings_path() -> Result<PathBuf, String> {
+    Ok(app_root()?.join(SETTINGS_FILE_NAME))
+}
+
+pub fn apply_lock_path() -> Result<PathBuf, String> {
+    Ok(app_root()?.join(APPLY_LOCK_FILE_NAME))
+}
+
+/// Resolves `~/...` and HOME-relative paths; absolute paths are kept as-is.
+pub fn expand_home(path: &str) -> Result<PathBuf, String> {
+    if path == "~" {
+        return home_dir();
+    }
+    if let Some(rest) = path.strip_prefix("~/") {
+        return Ok(home_dir()?.join(rest));
+    }
+    let candidate = PathBuf::from(path);
+    if candidate.is_absolute() {
+        Ok(candidate)
+    } else {
+        Ok(home_dir()?.join(candidate))
+    }
+}
+
 pub fn resolve_scope_base(scope: &str) -> Result<PathBuf, String> {
     match scope {
         "source" => source_root(),
-        "codex" => Ok(home_dir()?.join(".codex")),
-        "gemini" => Ok(home_dir()?.join(".gemini")),
-        "claude" => Ok(home_dir()?.join(".claude")),
-        _ => Err(format!("Unsupported scope: {scope}")),
+        _ => resolve_agent_root(scope),
     }
 }
 
 pub fn resolve_agent_root(agent: &str) -> Result<PathBuf, String> {
-    match agent {
-        "codex" | "gemini" | "claude" => resolve_scope_base(agent),
-        _ => Err(format!("Unsupported agent: {agent}")),
+    let registry = crate::agents::load_agent_registry()?;
+    match registry.find(agent) {
+        Some(definition) => definition.root_path(),
+        None => Err(format!("Unsupported agent: {agent}")),
     }
 }
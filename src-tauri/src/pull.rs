@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::agents::{load_agent_registry, AgentDefinition, AgentRegistry};
+use crate::diff::summarize_item;
+use crate::files::{
+    content_hash, list_files_recursive, normalize_relative_path, now_millis, read_text,
+    to_slash_path, write_atomic_bytes,
+};
+use crate::include::{has_includes, is_fragment};
+use crate::journal::{ApplyTransaction, JournalTarget};
+use crate::ledger::{ledger_for, save_ledgers, Ownership, OwnershipLedger};
+use crate::managed::extract_managed;
+use crate::mapping::{load_mapping, looks_like_file_mapping_path, MappingConfig, SyncMode};
+use crate::paths::source_root;
+use crate::sync::{find_by_stem, is_per_agent_source};
+use crate::template::{is_templated, load_template_vars, template_context};
+use crate::transform::category_transformer;
+use crate::types::{ApplySyncResult, DiffStats, PullPreview, PullSkip, SyncItem, SyncItemContent};
+use crate::workspace::ensure_workspace_layout;
+
+/// Where an agent-side file lands in source, and the content it gets.
+pub struct PullPlan {
+    pub target: String,
+    pub content: String,
+}
+
+/// Splits a composed per-agent target back into the `<agent>` override. Only
+/// possible while the shared base is still an intact prefix of the target.
+pub fn per_agent_pull_plan(
+    category: &str,
+    category_root: &Path,
+    agent: &str,
+    mapped_path: &str,
+    source_files: &[String],
+    target_content: &str,
+) -> Result<Option<PullPlan>, String> {
+    let base_content = match find_by_stem(source_files, "base") {
+        Some(f) => read_text(&category_root.join(f))?,
+        None => String::new(),
+    };
+    let base = base_content.trim_end();
+    let content = if base.is_empty() {
+        target_content.to_string()
+    } else {
+        match target_content.strip_prefix(base) {
+            Some(rest) => rest.trim_start_matches(['\r', '\n']).to_string(),
+            None => return Ok(None),
+        }
+    };
+
+    let agent_file = match find_by_stem(source_files, agent) {
+        Some(existing) => existing.clone(),
+        None => {
+            let extension = Path::new(mapped_path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("md");
+            format!("{agent}.{extension}")
+        }
+    };
+    Ok(Some(PullPlan {
+        target: format!("{category}/{agent_file}"),
+        content,
+    }))
+}
+
+fn pull_item(
+    agent: &str,
+    category: &str,
+    agent_relative: &Path,
+    plan: PullPlan,
+) -> Result<SyncItem, String> {
+    let source_rel = normalize_relative_path(&plan.target)?;
+    let source_rel_str = to_slash_path(&source_rel);
+    let source_abs = source_root()?.join(&source_rel);
+    let source_exists = source_abs.exists();
+    let before = if source_exists {
+        read_text(&source_abs)?
+    } else {
+        String::new()
+    };
+
+    let status = if !source_exists {
+        "create"
+    } else if before == plan.content {
+        "unchanged"
+    } else {
+        "update"
+    }
+    .to_string();
+
+    let mut item = SyncItem {
+        id: format!("{agent}:{category}:{source_rel_str}"),
+        agent: agent.to_string(),
+        category: category.to_string(),
+        source_file: to_slash_path(agent_relative),
+        target_relative_path: source_rel_str,
+        target_absolute_path: source_abs.display().to_string(),
+        status,
+        before_hash: String::new(),
+        after_hash: String::new(),
+        diff: String::new(),
+        diff_stats: DiffStats::default(),
+        before,
+        after: plan.content,
+        conflict: None,
+        error: None,
+        ownership: Ownership::Unowned,
+    };
+    summarize_item(&mut item);
+    Ok(item)
+}
+
+/// What an agent-side file contributes to a pull.
+enum AgentContent {
+    Text(String),
+    /// Managed target without an AgentDock block yet.
+    Empty,
+    Unsupported(String),
+}
+
+fn read_agent_content(
+    path: &Path,
+    category: &str,
+    sync_mode: &SyncMode,
+) -> Result<AgentContent, String> {
+    if matches!(sync_mode, SyncMode::Mcp) {
+        return Ok(AgentContent::Unsupported(
+            "MCP configs are merged per server; edit source/mcp instead".to_string(),
+        ));
+    }
+    let text = read_text(path)?;
+    if !matches!(sync_mode, SyncMode::Managed) {
+        return Ok(AgentContent::Text(text));
+    }
+    Ok(match extract_managed(&text, category, path) {
+        Ok(Some(block)) => AgentContent::Text(block),
+        Ok(None) => AgentContent::Empty,
+        Err(reason) => AgentContent::Unsupported(reason),
+    })
+}
+
+const TEMPLATED_REASON: &str = "Source file uses includes, template variables or conditionals";
+
+/// Pulling rendered output into such a source would flatten its includes and
+/// template syntax.
+fn is_templated_source(
+    path: &Path,
+    agent: &AgentDefinition,
+    vars: &BTreeMap<String, String>,
+) -> Result<bool, String> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+    let raw = read_text(path)?;
+    Ok(has_includes(&raw) || is_templated(&raw, &template_context(agent, vars)))
+}
+
+fn skip(agent: &str, category: &str, target: &str, reason: &str) -> PullSkip {
+    PullSkip {
+        agent: agent.to_string(),
+        category: category.to_string(),
+        target_relative_path: target.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Inverts the mapping: every agent-side target becomes a candidate write into
+/// source. `source_file` holds the agent-relative path being pulled and
+/// `target_*` the source file it would overwrite.
+pub fn build_pull_items(
+    mapping: &MappingConfig,
+    registry: &AgentRegistry,
+    agent_filter: Option<&str>,
+    category_filter: Option<&str>,
+) -> Result<(Vec<SyncItem>, Vec<PullSkip>), String> {
+    if let Some(agent) = agent_filter {
+        if registry.find(agent).is_none() {
+            return Err(format!("Unsupported agent: {agent}"));
+        }
+    }
+    if let Some(category) = category_filter {
+        if !mapping.categories.contains_key(category) {
+            return Err(format!("Unknown category: {category}"));
+        }
+    }
+
+    let src_root = source_root()?;
+    let vars = load_template_vars()?;
+    let mut items = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (category, target_mapping) in &mapping.categories {
+        if category_filter.is_some_and(|filter| filter != category) {
+            continue;
+        }
+
+        let category_root = src_root.join(category);
+        let source_files: Vec<String> = list_files_recursive(&category_root)?
+            .into_iter()
+            .filter(|file| !is_fragment(file, target_mapping.templates))
+            .collect();
+        let is_file_mapping = target_mapping
+            .targets
+            .values()
+            .any(|path| looks_like_file_mapping_path(path));
+        let per_agent = is_file_mapping
+            && (source_files.is_empty() || is_per_agent_source(&source_files, registry));
+        let transformer = category_transformer(category);
+
+        for definition in &registry.agents {
+            let agent = definition.id.as_str();
+            if agent_filter.is_some_and(|filter| filter != agent) {
+                continue;
+            }
+            let mapped_path = match target_mapping.target_for(agent) {
+                Some(path) => path,
+                None => continue,
+            };
+            let mapped_abs = definition.root_path()?.join(mapped_path);
+            if !mapped_abs.exists() {
+                continue;
+            }
+
+            if mapped_path.is_empty() {
+                skipped.push(skip(
+                    agent,
+                    category,
+                    mapped_path,
+                    "Category is mapped to the agent root and cannot be pulled",
+                ));
+                continue;
+            }
+            if matches!(target_mapping.sync_mode, SyncMode::Append) {
+                skipped.push(skip(
+                    agent,
+                    category,
+                    mapped_path,
+                    "Append-mode targets mix user content with synced content",
+                ));
+                continue;
+            }
+
+            if per_agent {
+                if !mapped_abs.is_file() {
+                    continue;
+                }
+                let content =
+                    match read_agent_content(&mapped_abs, category, &target_mapping.sync_mode)? {
+                        AgentContent::Text(text) => text,
+                        AgentContent::Empty => continue,
+                        AgentContent::Unsupported(reason) => {
+                            skipped.push(skip(agent, category, mapped_path, &reason));
+                            continue;
+                        }
+                    };
+                let plan = per_agent_pull_plan(
+                    category,
+                    &category_root,
+                    agent,
+                    mapped_path,
+                    &source_files,
+                    &content,
+                )?;
+                match plan {
+                    // Nothing agent-specific to adopt and no override to update.
+                    Some(plan)
+                        if plan.content.trim().is_empty()
+                            && find_by_stem(&source_files, agent).is_none() => {}
+                    Some(plan) => {
+                        items.push(pull_item(agent, category, Path::new(mapped_path), plan)?)
+                    }
+                    None => {
+                        let base_templated = match find_by_stem(&source_files, "base") {
+                            Some(base) if target_mapping.templates => {
+                                is_templated_source(&category_root.join(base), definition, &vars)?
+                            }
+                            _ => false,
+                        };
+                        let reason = if base_templated {
+                            TEMPLATED_REASON
+                        } else {
+                            "The shared base section was edited; move those edits into base manually"
+                        };
+                        skipped.push(skip(agent, category, mapped_path, reason));
+                    }
+                }
+            } else if looks_like_file_mapping_path(mapped_path) {
+                if !mapped_abs.is_file() {
+                    continue;
+                }
+                let content =
+                    match read_agent_content(&mapped_abs, category, &target_mapping.sync_mode)? {
+                        AgentContent::Text(text) => text,
+                        AgentContent::Empty => continue,
+                        AgentContent::Unsupported(reason) => {
+                            skipped.push(skip(agent, category, mapped_path, &reason));
+                            continue;
+                        }
+                    };
+                match source_files.as_slice() {
+                    [single] => {
+                        let plan = PullPlan {
+                            target: format!("{category}/{single}"),
+                            content,
+                        };
+                        items.push(pull_item(agent, category, Path::new(mapped_path), plan)?);
+                    }
+                    _ => skipped.push(skip(
+                        agent,
+                        category,
+                        mapped_path,
+                        "Several source files map to this target",
+                    )),
+                }
+            } else {
+                for relative in list_files_recursive(&mapped_abs)? {
+                    let agent_relative = Path::new(mapped_path).join(&relative);
+                    let file_abs = mapped_abs.join(&relative);
+                    let content =
+                        match read_agent_content(&file_abs, category, &target_mapping.sync_mode)? {
+                            AgentContent::Text(text) => text,
+                            AgentContent::Empty => continue,
+                            AgentContent::Unsupported(reason) => {
+                                let target = to_slash_path(&agent_relative);
+                                skipped.push(skip(agent, category, &target, &reason));
+                                continue;
+                            }
+                        };
+                    let (source_relative, content) = match &transformer {
+                        Some(transform) => {
+                            (transform.pull)(&category_root, &relative, &content, &source_files)?
+                        }
+                        None => (relative, content),
+                    };
+                    let plan = PullPlan {
+                        target: format!("{category}/{source_relative}"),
+                        content,
+                    };
+                    items.push(pull_item(agent, category, &agent_relative, plan)?);
+                }
+            }
+        }
+    }
+
+    let mut pullable = Vec::new();
+    for item in items {
+        let templates = mapping
+            .categories
+            .get(&item.category)
+            .is_some_and(|category| category.templates);
+        let templated = match registry.find(&item.agent) {
+            Some(definition) if templates => {
+                is_templated_source(Path::new(&item.target_absolute_path), definition, &vars)?
+            }
+            _ => false,
+        };
+        if templated {
+            skipped.push(skip(
+                &item.agent,
+                &item.category,
+                &item.source_file,
+                TEMPLATED_REASON,
+            ));
+        } else {
+            pullable.push(item);
+        }
+    }
+
+    pullable.sort_by(|a, b| {
+        a.agent
+            .cmp(&b.agent)
+            .then(a.target_relative_path.cmp(&b.target_relative_path))
+    });
+    Ok((pullable, skipped))
+}
+
+pub fn preview_pull_inner(
+    agent: Option<String>,
+    category: Option<String>,
+) -> Result<PullPreview, String> {
+    ensure_workspace_layout()?;
+    let mapping = load_mapping()?;
+    let registry = load_agent_registry()?;
+    let (items, skipped) =
+        build_pull_items(&mapping, &registry, agent.as_deref(), category.as_deref())?;
+    Ok(PullPreview {
+        generated_at: now_millis()?,
+        items,
+        skipped,
+    })
+}
+
+/// Full `before`/`after` of one item of the current pull preview.
+pub fn pull_item_content_inner(id: &str) -> Result<SyncItemContent, String> {
+    ensure_workspace_layout()?;
+    let mapping = load_mapping()?;
+    let registry = load_agent_registry()?;
+    let (items, _) = build_pull_items(&mapping, &registry, None, None)?;
+    let item = items
+        .into_iter()
+        .find(|item| item.id == id)
+        .ok_or_else(|| format!("Unknown pull item: {id}"))?;
+    Ok(SyncItemContent {
+        id: item.id,
+        before: item.before,
+        after: item.after,
+    })
+}
+
+pub fn apply_pull_inner(
+    agent: Option<String>,
+    category: Option<String>,
+    selected_ids: Vec<String>,
+) -> Result<ApplySyncResult, String> {
+    ensure_workspace_layout()?;
+    let mapping = load_mapping()?;
+    let registry = load_agent_registry()?;
+    let (all_items, _) =
+        build_pull_items(&mapping, &registry, agent.as_deref(), category.as_deref())?;
+
+    let selected: HashSet<String> = selected_ids.into_iter().collect();
+    let should_filter = !selected.is_empty();
+
+    let chosen: Vec<SyncItem> = all_items
+        .into_iter()
+        .filter(|item| item.status != "unchanged")
+        .filter(|item| !should_filter || selected.contains(&item.id))
+        .collect();
+
+    let mut seen = HashSet::new();
+    for item in &chosen {
+        if !seen.insert(item.target_relative_path.as_str()) {
+            return Err(format!(
+                "Several agents pull into the same source file, select only one: {}",
+                item.target_relative_path
+            ));
+        }
+    }
+
+    if chosen.is_empty() {
+        return Ok(ApplySyncResult {
+            backup_id: None,
+            applied_count: 0,
+            files: Vec::new(),
+            failure: None,
+        });
+    }
+
+    let targets: Vec<JournalTarget> = chosen
+        .iter()
+        .map(|item| JournalTarget {
+            agent: "source".to_string(),
+            relative: item.target_relative_path.clone(),
+            absolute: PathBuf::from(&item.target_absolute_path),
+        })
+        .collect();
+    let mut transaction = ApplyTransaction::begin("pull", &targets)?;
+    let mut ledgers = BTreeMap::new();
+    let applied_at = now_millis()?;
+    let outcome = write_pulled_sources(
+        &mut transaction,
+        &registry,
+        &chosen,
+        &targets,
+        &mut ledgers,
+        applied_at,
+    )
+    .and_then(|applied_files| {
+        save_ledgers(&mut ledgers, &registry)?;
+        Ok(applied_files)
+    });
+
+    match outcome {
+        Ok(applied_files) => {
+            let backup_id = transaction.commit_and_prune(Vec::new())?;
+            Ok(ApplySyncResult {
+                backup_id: Some(backup_id),
+                applied_count: applied_files.len(),
+                files: applied_files,
+                failure: None,
+            })
+        }
+        Err(error) => Ok(transaction.rollback(error)),
+    }
+}
+
+fn write_pulled_sources(
+    transaction: &mut ApplyTransaction,
+    registry: &AgentRegistry,
+    items: &[SyncItem],
+    sources: &[JournalTarget],
+    ledgers: &mut BTreeMap<String, OwnershipLedger>,
+    applied_at: u128,
+) -> Result<Vec<String>, String> {
+    let mut applied_files = Vec::new();
+    for (item, source) in items.iter().zip(sources) {
+        transaction.step(|| write_atomic_bytes(&source.absolute, item.after.as_bytes()))?;
+        applied_files.push(item.target_absolute_path.clone());
+
+        // The pulled agent file now reflects source, so it is no longer drift.
+        if let Some(definition) = registry.find(&item.agent) {
+            let agent_file = definition.root_path()?.join(&item.source_file);
+            let bytes = fs::read(&agent_file).map_err(|e| e.to_string())?;
+            ledger_for(ledgers, &item.agent)?.adopt_hash(
+                &item.source_file,
+                content_hash(&bytes),
+                applied_at,
+            );
+        }
+    }
+    Ok(applied_files)
+}
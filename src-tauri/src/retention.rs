@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+
+use crate::backup::{list_backups_inner, load_backup_manifest, write_backup_manifest};
+use crate::blobs::collect_garbage;
+use crate::files::{now_millis, write_atomic_bytes};
+use crate::journal::{with_apply_lock, ROLLBACK_TRIGGER};
+use crate::paths::{backups_root, retention_path};
+use crate::snapshot::SNAPSHOT_TRIGGER;
+use crate::types::{BackupInfo, PruneResult};
+use crate::workspace::ensure_workspace_layout;
+
+const DAY_MS: u128 = 24 * 60 * 60 * 1000;
+
+/// Which backups survive pruning (`retention.json`). A backup is kept when
+/// any rule keeps it; with no rules set nothing is pruned.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Keep the most recent N backups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_last: Option<usize>,
+    /// Keep every backup younger than N days.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_days: Option<u32>,
+    /// Keep the newest backup of each of the last N days.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_daily: Option<u32>,
+    /// Keep the newest backup of each of the last N weeks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_weekly: Option<u32>,
+}
+
+impl RetentionPolicy {
+    fn is_empty(&self) -> bool {
+        self.keep_last.is_none()
+            && self.keep_days.is_none()
+            && self.keep_daily.is_none()
+            && self.keep_weekly.is_none()
+    }
+}
+
+fn validate_retention_policy(policy: &RetentionPolicy) -> Result<(), String> {
+    let rules = [
+        ("keep_last", policy.keep_last.map(|value| value as u64)),
+        ("keep_days", policy.keep_days.map(u64::from)),
+        ("keep_daily", policy.keep_daily.map(u64::from)),
+        ("keep_weekly", policy.keep_weekly.map(u64::from)),
+    ];
+    match rules.iter().find(|(_, value)| *value == Some(0)) {
+        Some((name, _)) => Err(format!("{name} must be at least 1")),
+        None => Ok(()),
+    }
+}
+
+pub fn load_retention_policy() -> Result<RetentionPolicy, String> {
+    let path = retention_path()?;
+    if !path.exists() {
+        return Ok(RetentionPolicy::default());
+    }
+    let raw = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let policy = serde_json::from_str::<RetentionPolicy>(&raw)
+        .map_err(|e| format!("Invalid retention policy {}: {e}", path.display()))?;
+    validate_retention_policy(&policy)?;
+    Ok(policy)
+}
+
+pub fn save_retention_policy_inner(policy: &RetentionPolicy) -> Result<(), String> {
+    validate_retention_policy(policy)?;
+    ensure_workspace_layout()?;
+    let payload = serde_json::to_string_pretty(policy).map_err(|e| e.to_string())?;
+    write_atomic_bytes(&retention_path()?, payload.as_bytes())
+}
+
+/// Splits `backups` (newest first) into kept and prunable. Pinned backups,
+/// snapshots, labelled backups and ones kept after a failed rollback are
+/// never pruned.
+fn partition_backups(
+    policy: &RetentionPolicy,
+    backups: Vec<BackupInfo>,
+    now: u128,
+) -> (Vec<BackupInfo>, Vec<BackupInfo>) {
+    if policy.is_empty() {
+        return (backups, Vec::new());
+    }
+    let mut seen_days = HashSet::new();
+    let mut seen_weeks = HashSet::new();
+    let (mut kept, mut pruned) = (Vec::new(), Vec::new());
+    for (index, backup) in backups.into_iter().enumerate() {
+        let age_days = now.saturating_sub(backup.created_at) / DAY_MS;
+        let day = backup.created_at / DAY_MS;
+        let newest_of_day = seen_days.insert(day);
+        let newest_of_week = seen_weeks.insert(day / 7);
+        let keep = backup.pinned
+            || backup.label.is_some()
+            || backup.trigger == SNAPSHOT_TRIGGER
+            || backup.trigger == ROLLBACK_TRIGGER
+            || policy.keep_last.is_some_and(|count| index < count)
+            || policy
+                .keep_days
+                .is_some_and(|days| age_days < u128::from(days))
+            || policy
+                .keep_daily
+                .is_some_and(|days| newest_of_day && age_days < u128::from(days))
+            || policy
+                .keep_weekly
+                .is_some_and(|weeks| newest_of_week && age_days < u128::from(weeks) * 7);
+        if keep {
+            kept.push(backup);
+        } else {
+            pruned.push(backup);
+        }
+    }
+    (kept, pruned)
+}
+
+/// Applies the retention policy. With `dry_run` only reports what would go.
+pub fn prune_backups_inner(dry_run: bool) -> Result<PruneResult, String> {
+    if dry_run {
+        return prune_backups(true);
+    }
+    with_apply_lock(|| prune_backups(false))
+}
+
+/// `prune_backups_inner` for callers that hold the apply lock.
+fn prune_backups(dry_run: bool) -> Result<PruneResult, String> {
+    let policy = load_retention_policy()?;
+    let (kept, pruned) = partition_backups(&policy, list_backups_inner()?, now_millis()?);
+    if !dry_run {
+        let root = backups_root()?;
+        for backup in &pruned {
+            fs::remove_dir_all(root.join(&backup.backup_id)).map_err(|e| e.to_string())?;
+        }
+        collect_garbage()?;
+    }
+    Ok(PruneResult {
+        dry_run,
+        pruned,
+        kept,
+    })
+}
+
+/// Prunes after an apply, before it releases the apply lock. Best effort:
+/// the apply has already succeeded.
+pub fn enforce_retention() {
+    let _ = prune_backups(false);
+}
+
+pub fn set_backup_pinned_inner(backup_id: &str, pinned: bool) -> Result<(), String> {
+    ensure_workspace_layout()?;
+    let (backup_dir, mut manifest) =
+        load_backup_manifest(backup_id).map_err(|_| format!("Backup not found: {backup_id}"))?;
+    manifest.pinned = pinned;
+    write_backup_manifest(&backup_dir, &manifest)
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::files::write_atomic_bytes;
+use crate::paths::settings_path;
+use crate::workspace::ensure_workspace_layout;
+
+/// How newly stored backup blobs are written. Existing blobs keep the
+/// format they were written in.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// Key material for encrypted backups; none of it is secret. Blobs are
+/// sealed to `public_key`, whose private key is kept encrypted under a key
+/// derived from the passphrase with `salt`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncryptionSettings {
+    pub public_key: String,
+    pub salt: String,
+    pub wrapped_key: String,
+}
+
+/// App-wide preferences (`settings.json`).
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Settings {
+    #[serde(default)]
+    pub backup_compression: BackupCompression,
+    /// Set while backups are encrypted; changed only by enabling or
+    /// disabling encryption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_encryption: Option<EncryptionSettings>,
+}
+
+pub fn load_settings() -> Result<Settings, String> {
+    let path = settings_path()?;
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let raw = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str::<Settings>(&raw)
+        .map_err(|e| format!("Invalid settings {}: {e}", path.display()))
+}
+
+pub fn write_settings(settings: &Settings) -> Result<(), String> {
+    ensure_workspace_layout()?;
+    let payload = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    write_atomic_bytes(&settings_path()?, payload.as_bytes())
+}
+
+/// Saves user-editable settings; the encryption keys are left as they are.
+pub fn save_settings_inner(settings: &Settings) -> Result<(), String> {
+    write_settings(&Settings {
+        backup_encryption: load_settings()?.backup_encryption,
+        ..settings.clone()
+    })
+}
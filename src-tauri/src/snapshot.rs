@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::agents::load_agent_registry;
+use crate::backup::{
+    backup_existing_file, backup_info, create_backup_dir, list_backup_manifests,
+    load_backup_manifest, write_backup_manifest,
+};
+use crate::files::{list_files_recursive, now_millis, to_slash_path};
+use crate::journal::with_apply_lock;
+use crate::mapping::load_mapping;
+use crate::paths::source_root;
+use crate::types::{BackupEntry, BackupInfo, BackupManifest, BackupQuery};
+use crate::workspace::ensure_workspace_layout;
+
+pub const SNAPSHOT_TRIGGER: &str = "snapshot";
+
+fn clean_text(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Trimmed, non-empty and unique, in the order given.
+fn clean_tags(tags: Vec<String>) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    tags.into_iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty() && seen.insert(tag.clone()))
+        .collect()
+}
+
+/// `relative` under the mapped `target`, relative to the agent root. An empty
+/// target is the agent root itself.
+fn target_file_path(target: &str, relative: &str) -> String {
+    to_slash_path(&Path::new(target).join(relative))
+}
+
+/// `(agent, relative path)` of every existing file under a mapped target,
+/// plus every file of the source tree.
+fn snapshot_files() -> Result<BTreeSet<(String, String)>, String> {
+    let mapping = load_mapping()?;
+    let registry = load_agent_registry()?;
+    let mut files = BTreeSet::new();
+    for category in mapping.categories.values() {
+        for agent in &registry.agents {
+            let Some(target) = category.target_for(&agent.id) else {
+                continue;
+            };
+            let target = target.trim_end_matches('/');
+            let absolute = agent.root_path()?.join(target);
+            if absolute.is_file() {
+                files.insert((agent.id.clone(), target.to_string()));
+                continue;
+            }
+            for relative in list_files_recursive(&absolute)? {
+                files.insert((agent.id.clone(), target_file_path(target, &relative)));
+            }
+        }
+    }
+    for relative in list_files_recursive(&source_root()?)? {
+        files.insert(("source".to_string(), relative));
+    }
+    Ok(files)
+}
+
+/// Backs up every mapped target file and the whole source tree as a
+/// labelled snapshot.
+pub fn create_snapshot_inner(
+    label: Option<String>,
+    note: Option<String>,
+    tags: Vec<String>,
+) -> Result<BackupInfo, String> {
+    ensure_workspace_layout()?;
+    let registry = load_agent_registry()?;
+    let src_root = source_root()?;
+    // Under the lock, so garbage collection cannot drop the blobs stored
+    // before the manifest that references them is written.
+    let manifest = with_apply_lock(|| {
+        let mut entries: Vec<BackupEntry> = Vec::new();
+        for (agent, relative) in snapshot_files()? {
+            let base = match registry.find(&agent) {
+                Some(definition) => definition.root_path()?,
+                None => src_root.clone(),
+            };
+            entries.push(backup_existing_file(
+                &agent,
+                &relative,
+                &base.join(&relative),
+            )?);
+        }
+
+        let (backup_id, backup_dir) = create_backup_dir()?;
+        let manifest = BackupManifest {
+            label: clean_text(label),
+            note: clean_text(note),
+            tags: clean_tags(tags),
+            ..BackupManifest::new(backup_id, now_millis()?, SNAPSHOT_TRIGGER, entries)
+        };
+        write_backup_manifest(&backup_dir, &manifest)?;
+        Ok(manifest)
+    })?;
+    backup_info(&manifest)
+}
+
+/// Replaces the label, note and tags of a backup. Empty values clear them.
+pub fn annotate_backup_inner(
+    backup_id: &str,
+    label: Option<String>,
+    note: Option<String>,
+    tags: Vec<String>,
+) -> Result<BackupInfo, String> {
+    ensure_workspace_layout()?;
+    let (backup_dir, mut manifest) =
+        load_backup_manifest(backup_id).map_err(|_| format!("Backup not found: {backup_id}"))?;
+    manifest.label = clean_text(label);
+    manifest.note = clean_text(note);
+    manifest.tags = clean_tags(tags);
+    write_backup_manifest(&backup_dir, &manifest)?;
+    backup_info(&manifest)
+}
+
+fn contains_ignore_case(haystack: Option<&str>, needle: &str) -> bool {
+    haystack.is_some_and(|haystack| haystack.to_lowercase().contains(&needle.to_lowercase()))
+}
+
+fn matches_query(query: &BackupQuery, manifest: &BackupManifest) -> bool {
+    query
+        .trigger
+        .as_ref()
+        .is_none_or(|trigger| &manifest.trigger == trigger)
+        && query.label.as_ref().is_none_or(|label| {
+            contains_ignore_case(manifest.label.as_deref(), label)
+                || contains_ignore_case(manifest.note.as_deref(), label)
+        })
+        && query
+            .tag
+            .as_ref()
+            .is_none_or(|tag| manifest.tags.contains(tag))
+        && query
+            .agent
+            .as_ref()
+            .is_none_or(|agent| manifest.entries.iter().any(|entry| &entry.agent == agent))
+        && query.path.as_ref().is_none_or(|path| {
+            manifest.entries.iter().any(|entry| {
+                entry.target_relative_path.contains(path.as_str())
+                    || entry.target_absolute_path.contains(path.as_str())
+            })
+        })
+}
+
+/// Backups matching every field set in `query`, newest first.
+pub fn search_backups_inner(query: &BackupQuery) -> Result<Vec<BackupInfo>, String> {
+    list_backup_manifests()?
+        .iter()
+        .filter(|manifest| matches_query(query, manifest))
+        .map(backup_info)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_target_files_relative_to_the_agent_root() {
+        assert_eq!(
+            target_file_path("skills", "a/SKILL.md"),
+            "skills/a/SKILL.md"
+        );
+        assert_eq!(target_file_path("", "AGENTS.md"), "AGENTS.md");
+        assert_eq!(target_file_path("", "rules/a.md"), "rules/a.md");
+    }
+}
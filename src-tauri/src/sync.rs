@@ -1,46 +1,102 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use crate::agents::{AgentDefinition, AgentRegistry};
+use crate::backup::backup_entry;
+use crate::diff::summarize_item;
 use crate::files::{
-    list_files_recursive, now_millis, read_text, to_slash_path, write_atomic_bytes,
+    content_hash, list_files_recursive, normalize_relative_path, now_millis, read_text,
+    remove_file_and_empty_parents, to_slash_path, write_atomic_bytes,
 };
+use crate::include::{expand_includes, is_fragment};
+use crate::journal::{ApplyTransaction, JournalTarget};
+use crate::ledger::{
+    ledger_for, load_ledgers, save_ledgers, LedgerEntry, Ownership, OwnershipLedger,
+};
+use crate::managed::{render_managed, strip_managed};
 use crate::mapping::{looks_like_file_mapping_path, MappingConfig, SyncMode};
-use crate::paths::{backups_root, resolve_agent_root, source_root};
+use crate::mcp::{render_mcp, strip_mcp};
+use crate::paths::source_root;
+use crate::pull::{per_agent_pull_plan, PullPlan};
+use crate::template::{is_templated, load_template_vars, render_template, template_context};
+use crate::transform::category_transformer;
 use crate::types::{
-    ApplySyncResult, BackupEntry, BackupManifest, SourcePromptSnapshot, SyncItem, SyncPreview,
+    ApplySyncResult, BackupEntry, ConflictResolution, DiffStats, SourcePromptSnapshot,
+    SyncConflict, SyncItem, SyncItemContent, SyncItemHashes, SyncPreview,
 };
 
-const AGENT_NAMES: [&str; 3] = ["codex", "gemini", "claude"];
-const SOURCE_PROMPT_FILES: [&str; 4] = [
-    "instructions/base.md",
-    "instructions/codex.md",
-    "instructions/gemini.md",
-    "instructions/claude.md",
-];
+/// Instruction prompt files snapshotted into every sync backup: the shared
+/// base plus one override per registered agent.
+pub fn source_prompt_files(registry: &AgentRegistry) -> Vec<String> {
+    let mut files = vec!["instructions/base.md".to_string()];
+    for agent in &registry.agents {
+        files.push(format!("instructions/{}.md", agent.id));
+    }
+    files
+}
 
-fn is_per_agent_source(source_files: &[String]) -> bool {
+pub fn is_per_agent_source(source_files: &[String], registry: &AgentRegistry) -> bool {
     source_files.iter().any(|f| {
         let stem = Path::new(f)
             .file_stem()
             .and_then(|s| s.to_str())
             .unwrap_or("");
-        stem == "base" || AGENT_NAMES.contains(&stem)
+        stem == "base" || registry.find(stem).is_some()
     })
 }
 
+pub fn find_by_stem<'a>(source_files: &'a [String], stem: &str) -> Option<&'a String> {
+    source_files
+        .iter()
+        .find(|f| Path::new(f).file_stem().and_then(|s| s.to_str()) == Some(stem))
+}
+
+/// Source content for one target and every file it was built from.
+struct ComposedSource {
+    /// Text with `@include`s expanded, or why expansion failed.
+    content: Result<String, String>,
+    files: Vec<String>,
+    /// Whether fragments were included; such targets cannot be pulled back.
+    expanded: bool,
+}
+
+impl ComposedSource {
+    fn description(&self) -> String {
+        self.files.join(" + ")
+    }
+}
+
+/// Expands `@include` lines when the category opts in to templates.
+fn compose_source_file(
+    category: &str,
+    relative: &str,
+    raw: &str,
+    templates: bool,
+) -> ComposedSource {
+    let origin = format!("{category}/{relative}");
+    let mut files = vec![origin.clone()];
+    let content = if templates {
+        expand_includes(raw, category, &origin, &mut files)
+    } else {
+        Ok(raw.to_string())
+    };
+    ComposedSource {
+        content,
+        expanded: files.len() > 1,
+        files,
+    }
+}
+
 fn compose_per_agent_content(
     category: &str,
     category_root: &Path,
     agent: &str,
     source_files: &[String],
-) -> Result<Option<(String, String)>, String> {
-    let base_file = source_files
-        .iter()
-        .find(|f| Path::new(f).file_stem().and_then(|s| s.to_str()) == Some("base"));
-    let agent_file = source_files
-        .iter()
-        .find(|f| Path::new(f).file_stem().and_then(|s| s.to_str()) == Some(agent));
+    templates: bool,
+) -> Result<Option<ComposedSource>, String> {
+    let base_file = find_by_stem(source_files, "base");
+    let agent_file = find_by_stem(source_files, agent);
 
     let base_content = match base_file {
         Some(f) => read_text(&category_root.join(f))?,
@@ -51,39 +107,97 @@ fn compose_per_agent_content(
         None => String::new(),
     };
 
-    let base_empty = base_content.trim().is_empty();
-    let agent_empty = agent_content.trim().is_empty();
-
-    if base_empty && agent_empty {
+    if base_content.trim().is_empty() && agent_content.trim().is_empty() {
         return Ok(None);
     }
 
-    let composed = if base_empty {
-        agent_content
-    } else if agent_empty {
-        base_content
-    } else {
-        format!("{}\n\n{}", base_content.trim_end(), agent_content)
-    };
+    let base = base_file.map(|f| compose_source_file(category, f, &base_content, templates));
+    let agent_part =
+        agent_file.map(|f| compose_source_file(category, f, &agent_content, templates));
+    let mut files = Vec::new();
+    let mut expanded = false;
+    let mut parts = Vec::new();
+    for part in [base, agent_part].into_iter().flatten() {
+        files.extend(part.files);
+        expanded |= part.expanded;
+        parts.push(part.content);
+    }
 
-    let desc = match (base_file, agent_file) {
-        (Some(b), Some(a)) => format!("{category}/{b} + {category}/{a}"),
-        (Some(b), None) => format!("{category}/{b}"),
-        (None, Some(a)) => format!("{category}/{a}"),
-        (None, None) => unreachable!(),
-    };
+    let content = parts
+        .into_iter()
+        .collect::<Result<Vec<String>, String>>()
+        .map(|parts| {
+            let parts: Vec<&String> = parts.iter().filter(|p| !p.trim().is_empty()).collect();
+            match parts.as_slice() {
+                [base, agent] => format!("{}\n\n{}", base.trim_end(), agent),
+                [single] => single.to_string(),
+                _ => String::new(),
+            }
+        });
+
+    Ok(Some(ComposedSource {
+        content,
+        files,
+        expanded,
+    }))
+}
+
+/// Renders template tags when the category opts in to templates.
+fn render_source(
+    text: String,
+    templates: bool,
+    context: &BTreeMap<String, String>,
+) -> Result<String, String> {
+    if templates {
+        render_template(&text, context)
+    } else {
+        Ok(text)
+    }
+}
 
-    Ok(Some((composed, desc)))
+/// Where a rendered source file is about to be written.
+struct SyncTarget<'a> {
+    agent: &'a AgentDefinition,
+    category: &'a str,
+    relative: &'a Path,
+    exists: bool,
+    owned: Option<&'a LedgerEntry>,
 }
 
+/// Renders `after` into the target per `sync_mode`. Returns the content to
+/// write, the item status and what AgentDock then owns of the target.
 fn apply_sync_mode(
     before: &str,
     after: String,
     sync_mode: &SyncMode,
-    target_exists: bool,
-) -> (String, String) {
+    target: &SyncTarget,
+) -> Result<(String, String, Ownership), String> {
+    let mut ownership = match sync_mode {
+        SyncMode::Replace => Ownership::File,
+        SyncMode::Append | SyncMode::Managed | SyncMode::Mcp => Ownership::Unowned,
+    };
     let after_content = match sync_mode {
         SyncMode::Replace => after,
+        SyncMode::Managed => {
+            let previous = target
+                .owned
+                .map(|entry| entry.managed_keys.as_slice())
+                .unwrap_or_default();
+            let (rendered, keys) =
+                render_managed(before, &after, target.category, target.relative, previous)?;
+            ownership = Ownership::Managed(keys);
+            rendered
+        }
+        SyncMode::Mcp => {
+            let previous = target
+                .owned
+                .and_then(|entry| entry.mcp_servers.clone())
+                .unwrap_or_default();
+            let (rendered, servers) =
+                render_mcp(before, &after, target.agent, target.relative, &previous)?;
+            ownership = Ownership::Mcp(servers);
+            rendered
+        }
         SyncMode::Append => {
             if before.is_empty() {
                 after
@@ -95,22 +209,102 @@ fn apply_sync_mode(
         }
     };
 
-    let status = if !target_exists {
+    let status = if !target.exists {
         "create"
     } else if before == after_content {
         "unchanged"
     } else if matches!(sync_mode, SyncMode::Append) {
         "append"
+    } else if matches!(sync_mode, SyncMode::Managed | SyncMode::Mcp) {
+        "merge"
     } else {
         "update"
     }
     .to_string();
 
-    (after_content, status)
+    Ok((after_content, status, ownership))
 }
 
-pub fn build_sync_items(mapping: &MappingConfig) -> Result<Vec<SyncItem>, String> {
+/// A failed render becomes an `error` item that leaves the target untouched.
+fn settle_render(
+    before: &str,
+    rendered: Result<(String, String, Ownership), String>,
+) -> (String, String, Ownership, Option<String>) {
+    match rendered {
+        Ok((content, status, ownership)) => (content, status, ownership, None),
+        Err(error) => (
+            before.to_string(),
+            "error".to_string(),
+            Ownership::Unowned,
+            Some(error),
+        ),
+    }
+}
+
+/// Compares the target against the hash recorded when AgentDock last wrote
+/// it. Returns `(recorded, current)` hashes when they differ.
+fn detect_drift(
+    ledger: Option<&OwnershipLedger>,
+    item: &SyncItem,
+) -> Result<Option<(String, String)>, String> {
+    if !matches!(item.status.as_str(), "update" | "append" | "delete") {
+        return Ok(None);
+    }
+    let recorded = match ledger
+        .and_then(|ledger| ledger.entries.get(&item.target_relative_path))
+        .and_then(|entry| entry.hash.clone())
+    {
+        Some(hash) => hash,
+        None => return Ok(None),
+    };
+    let current_bytes = fs::read(&item.target_absolute_path).map_err(|e| e.to_string())?;
+    let current = content_hash(&current_bytes);
+    if current == recorded {
+        Ok(None)
+    } else {
+        Ok(Some((recorded, current)))
+    }
+}
+
+fn owned_entry<'a>(
+    ledgers: &'a BTreeMap<String, OwnershipLedger>,
+    agent: &str,
+    target_relative_path: &str,
+) -> Option<&'a LedgerEntry> {
+    ledgers
+        .get(agent)
+        .and_then(|ledger| ledger.entries.get(target_relative_path))
+}
+
+fn flag_conflict(item: &mut SyncItem, drift: (String, String), pull: Option<PullPlan>) {
+    let (recorded_hash, current_hash) = drift;
+    let mut resolutions = vec![ConflictResolution::Overwrite, ConflictResolution::Skip];
+    if pull.is_some() {
+        resolutions.push(ConflictResolution::Pull);
+    }
+    let planned_status = std::mem::replace(&mut item.status, "conflict".to_string());
+    item.conflict = Some(SyncConflict {
+        planned_status,
+        recorded_hash,
+        current_hash,
+        pull_target: pull.as_ref().map(|plan| plan.target.clone()),
+        resolutions,
+        pull_content: pull.map(|plan| plan.content),
+    });
+}
+
+pub fn build_sync_items(
+    mapping: &MappingConfig,
+    registry: &AgentRegistry,
+) -> Result<Vec<SyncItem>, String> {
     let src_root = source_root()?;
+    let ledgers = load_ledgers(registry)?;
+    let vars = load_template_vars()?;
+    let contexts: HashMap<&str, BTreeMap<String, String>> = registry
+        .agents
+        .iter()
+        .map(|agent| (agent.id.as_str(), template_context(agent, &vars)))
+        .collect();
     let mut items = Vec::new();
     let mut planned_targets = HashSet::new();
 
@@ -119,31 +313,47 @@ pub fn build_sync_items(mapping: &MappingConfig) -> Result<Vec<SyncItem>, String
         if !category_root.exists() {
             continue;
         }
+        let templates = target_mapping.templates;
 
-        let source_files = list_files_recursive(&category_root)?;
+        let source_files: Vec<String> = list_files_recursive(&category_root)?
+            .into_iter()
+            .filter(|file| !is_fragment(file, templates))
+            .collect();
         if source_files.is_empty() {
             continue;
         }
 
-        let is_file_mapping = looks_like_file_mapping_path(&target_mapping.codex);
-        let per_agent = is_file_mapping && is_per_agent_source(&source_files);
+        let targets: Vec<(&AgentDefinition, &str)> = registry
+            .agents
+            .iter()
+            .filter_map(|agent| {
+                target_mapping
+                    .target_for(&agent.id)
+                    .map(|path| (agent, path))
+            })
+            .collect();
 
-        let targets = [
-            ("codex", target_mapping.codex.as_str()),
-            ("gemini", target_mapping.gemini.as_str()),
-            ("claude", target_mapping.claude.as_str()),
-        ];
+        let is_file_mapping = targets
+            .iter()
+            .any(|(_, path)| looks_like_file_mapping_path(path));
+        let per_agent = is_file_mapping && is_per_agent_source(&source_files, registry);
 
         if per_agent {
-            for (agent, mapped_path) in &targets {
-                let composed =
-                    compose_per_agent_content(category, &category_root, agent, &source_files)?;
-                let (after, source_desc) = match composed {
-                    Some(v) => v,
-                    None => continue,
+            for (definition, mapped_path) in &targets {
+                let agent = definition.id.as_str();
+                let composed = compose_per_agent_content(
+                    category,
+                    &category_root,
+                    agent,
+                    &source_files,
+                    templates,
+                )?;
+                let Some(composed) = composed else {
+                    continue;
                 };
+                let context = &contexts[agent];
 
-                let agent_root = resolve_agent_root(agent)?;
+                let agent_root = definition.root_path()?;
                 let target_rel = PathBuf::from(mapped_path);
                 let target_rel_str = to_slash_path(&target_rel);
                 let target_key = format!("{agent}:{target_rel_str}");
@@ -157,30 +367,76 @@ pub fn build_sync_items(mapping: &MappingConfig) -> Result<Vec<SyncItem>, String
                     String::new()
                 };
 
-                let (after_content, status) =
-                    apply_sync_mode(&before, after, &target_mapping.sync_mode, target_exists);
+                let target = SyncTarget {
+                    agent: definition,
+                    category,
+                    relative: &target_rel,
+                    exists: target_exists,
+                    owned: owned_entry(&ledgers, agent, &target_rel_str),
+                };
+                let rendered = composed
+                    .content
+                    .clone()
+                    .and_then(|text| render_source(text, templates, context))
+                    .and_then(|after| {
+                        apply_sync_mode(&before, after, &target_mapping.sync_mode, &target)
+                    });
+                let (after_content, status, ownership, error) = settle_render(&before, rendered);
 
                 let id = format!("{agent}:{category}:{target_rel_str}");
-                items.push(SyncItem {
+                let mut item = SyncItem {
                     id,
                     agent: agent.to_string(),
                     category: category.to_string(),
-                    source_file: source_desc,
+                    source_file: composed.description(),
                     target_relative_path: target_rel_str,
                     target_absolute_path: target_abs.display().to_string(),
                     status,
                     before,
                     after: after_content,
-                });
+                    before_hash: String::new(),
+                    after_hash: String::new(),
+                    diff: String::new(),
+                    diff_stats: DiffStats::default(),
+                    conflict: None,
+                    error,
+                    ownership,
+                };
+                if let Some(drift) = detect_drift(ledgers.get(agent), &item)? {
+                    let pullable = match &composed.content {
+                        Ok(_) if composed.expanded => false,
+                        Ok(text) => !(templates && is_templated(text, context)),
+                        Err(_) => false,
+                    };
+                    let pull = if !pullable {
+                        None
+                    } else {
+                        per_agent_pull_plan(
+                            category,
+                            &category_root,
+                            agent,
+                            mapped_path,
+                            &source_files,
+                            &item.before,
+                        )?
+                    };
+                    flag_conflict(&mut item, drift, pull);
+                }
+                items.push(item);
             }
         } else {
+            let transformer = category_transformer(category);
             for relative_under_category in &source_files {
                 let source_abs = category_root.join(relative_under_category);
-                let after = read_text(&source_abs)?;
+                let raw = read_text(&source_abs)?;
                 let source_file = format!("{category}/{relative_under_category}");
+                let composed =
+                    compose_source_file(category, relative_under_category, &raw, templates);
 
-                for (agent, subdir) in &targets {
-                    let agent_root = resolve_agent_root(agent)?;
+                for (definition, subdir) in &targets {
+                    let agent = definition.id.as_str();
+                    let context = &contexts[agent];
+                    let agent_root = definition.root_path()?;
                     let target_rel = if subdir.is_empty() {
                         PathBuf::from(relative_under_category)
                     } else if looks_like_file_mapping_path(subdir) {
@@ -188,6 +444,22 @@ pub fn build_sync_items(mapping: &MappingConfig) -> Result<Vec<SyncItem>, String
                     } else {
                         Path::new(subdir).join(relative_under_category)
                     };
+                    let transform = transformer
+                        .as_ref()
+                        .filter(|_| !looks_like_file_mapping_path(subdir));
+                    let rendered = composed
+                        .content
+                        .clone()
+                        .and_then(|text| render_source(text, templates, context));
+                    let (target_rel, rendered) = match (transform, rendered) {
+                        (Some(transform), Ok(after)) => {
+                            match (transform.render)(definition, target_rel.clone(), after) {
+                                Ok((path, after)) => (path, Ok(after)),
+                                Err(error) => (target_rel, Err(error)),
+                            }
+                        }
+                        (_, rendered) => (target_rel, rendered),
+                    };
 
                     let target_rel_str = to_slash_path(&target_rel);
                     let target_key = format!("{agent}:{target_rel_str}");
@@ -206,30 +478,75 @@ pub fn build_sync_items(mapping: &MappingConfig) -> Result<Vec<SyncItem>, String
                         String::new()
                     };
 
-                    let (after_content, status) = apply_sync_mode(
-                        &before,
-                        after.clone(),
-                        &target_mapping.sync_mode,
-                        target_exists,
-                    );
+                    let target = SyncTarget {
+                        agent: definition,
+                        category,
+                        relative: &target_rel,
+                        exists: target_exists,
+                        owned: owned_entry(&ledgers, agent, &target_rel_str),
+                    };
+                    let rendered = rendered.and_then(|after| {
+                        apply_sync_mode(&before, after, &target_mapping.sync_mode, &target)
+                    });
+                    let (after_content, status, ownership, error) =
+                        settle_render(&before, rendered);
 
                     let id = format!("{agent}:{category}:{target_rel_str}");
-                    items.push(SyncItem {
+                    let mut item = SyncItem {
                         id,
                         agent: agent.to_string(),
                         category: category.to_string(),
-                        source_file: source_file.clone(),
+                        source_file: composed.description(),
                         target_relative_path: target_rel_str,
                         target_absolute_path: target_abs.display().to_string(),
                         status,
                         before,
                         after: after_content,
-                    });
+                        before_hash: String::new(),
+                        after_hash: String::new(),
+                        diff: String::new(),
+                        diff_stats: DiffStats::default(),
+                        conflict: None,
+                        error,
+                        ownership,
+                    };
+                    if let Some(drift) = detect_drift(ledgers.get(agent), &item)? {
+                        let pull = match transform {
+                            _ if composed.expanded
+                                || (templates && is_templated(&raw, context)) =>
+                            {
+                                None
+                            }
+                            Some(transform) => {
+                                let agent_relative =
+                                    target_rel.strip_prefix(subdir).map_err(|e| e.to_string())?;
+                                let (source_relative, content) = (transform.pull)(
+                                    &category_root,
+                                    &to_slash_path(agent_relative),
+                                    &item.before,
+                                    &source_files,
+                                )?;
+                                Some(PullPlan {
+                                    target: format!("{category}/{source_relative}"),
+                                    content,
+                                })
+                            }
+                            None => Some(PullPlan {
+                                target: source_file.clone(),
+                                content: item.before.clone(),
+                            }),
+                        };
+                        flag_conflict(&mut item, drift, pull);
+                    }
+                    items.push(item);
                 }
             }
         }
     }
 
+    build_orphan_items(registry, &ledgers, &planned_targets, &mut items)?;
+
+    items.iter_mut().for_each(summarize_item);
     items.sort_by(|a, b| {
         a.agent
             .cmp(&b.agent)
@@ -238,145 +555,497 @@ pub fn build_sync_items(mapping: &MappingConfig) -> Result<Vec<SyncItem>, String
     Ok(items)
 }
 
+/// Ids of items that would be applied but differ from what the preview
+/// showed (or were not in it), plus selected ids that no longer exist.
+fn stale_item_ids(
+    items: &[SyncItem],
+    selected: &HashSet<String>,
+    expected: &HashMap<String, SyncItemHashes>,
+) -> Vec<String> {
+    let mut stale: Vec<String> = items
+        .iter()
+        .filter(|item| item.status != "unchanged" && item.status != "error")
+        .filter(|item| selected.is_empty() || selected.contains(&item.id))
+        .filter(|item| match expected.get(&item.id) {
+            Some(hashes) => {
+                hashes.before_hash != item.before_hash || hashes.after_hash != item.after_hash
+            }
+            None => true,
+        })
+        .map(|item| item.id.clone())
+        .collect();
+    let mut vanished: Vec<String> = selected
+        .iter()
+        .filter(|id| !items.iter().any(|item| &item.id == *id))
+        .cloned()
+        .collect();
+    vanished.sort();
+    stale.extend(vanished);
+    stale
+}
+
+/// What is left of an orphaned target once AgentDock's part is removed, and
+/// the item status: owned files are deleted, `managed` targets lose their
+/// marker block or merged keys and MCP configs the servers merged into them.
+/// `None` when there is nothing left to remove.
+fn strip_orphan(
+    definition: &AgentDefinition,
+    target_rel: &Path,
+    owned: &LedgerEntry,
+    before: &str,
+) -> Result<Option<(String, &'static str)>, String> {
+    let after = if let Some(servers) = &owned.mcp_servers {
+        strip_mcp(before, definition, target_rel, servers)?
+    } else if owned.managed {
+        strip_managed(before, &owned.category, target_rel, &owned.managed_keys)?
+    } else {
+        return Ok(Some((String::new(), "delete")));
+    };
+    if after == before {
+        Ok(None)
+    } else if after.trim().is_empty() {
+        Ok(Some((String::new(), "delete")))
+    } else {
+        Ok(Some((after, "merge")))
+    }
+}
+
+/// Emits an item removing AgentDock's content from every target it owns that
+/// no source file maps to anymore.
+fn build_orphan_items(
+    registry: &AgentRegistry,
+    ledgers: &BTreeMap<String, OwnershipLedger>,
+    planned_targets: &HashSet<String>,
+    items: &mut Vec<SyncItem>,
+) -> Result<(), String> {
+    let src_root = source_root()?;
+    for definition in &registry.agents {
+        let agent = definition.id.as_str();
+        let ledger = match ledgers.get(agent) {
+            Some(ledger) if !ledger.entries.is_empty() => ledger,
+            _ => continue,
+        };
+
+        let agent_root = definition.root_path()?;
+        for (owned_path, owned) in &ledger.entries {
+            let target_rel = normalize_relative_path(owned_path)?;
+            let target_rel_str = to_slash_path(&target_rel);
+            if planned_targets.contains(&format!("{agent}:{target_rel_str}")) {
+                continue;
+            }
+
+            let target_abs = agent_root.join(&target_rel);
+            if !target_abs.is_file() {
+                continue;
+            }
+
+            let before = read_text(&target_abs)?;
+            let (after, status, error) = match strip_orphan(definition, &target_rel, owned, &before)
+            {
+                Ok(Some((after, status))) => (after, status.to_string(), None),
+                Ok(None) => continue,
+                Err(error) => (before.clone(), "error".to_string(), Some(error)),
+            };
+            let mut item = SyncItem {
+                id: format!("{agent}:{}:{target_rel_str}", owned.category),
+                agent: agent.to_string(),
+                category: owned.category.clone(),
+                source_file: owned.source_file.clone(),
+                target_relative_path: target_rel_str,
+                target_absolute_path: target_abs.display().to_string(),
+                status,
+                before,
+                after,
+                before_hash: String::new(),
+                after_hash: String::new(),
+                diff: String::new(),
+                diff_stats: DiffStats::default(),
+                conflict: None,
+                error,
+                ownership: Ownership::Released,
+            };
+            if let Some(drift) = detect_drift(Some(ledger), &item)? {
+                // Only a single removed source file can be recreated from the target.
+                let pull = normalize_relative_path(&owned.source_file)
+                    .ok()
+                    .filter(|source_rel| !src_root.join(source_rel).exists())
+                    .map(|_| PullPlan {
+                        target: owned.source_file.clone(),
+                        content: item.before.clone(),
+                    });
+                flag_conflict(&mut item, drift, pull);
+            }
+            items.push(item);
+        }
+    }
+    Ok(())
+}
+
+/// Records what AgentDock owns of each written target and drops released
+/// entries. Only `replace` targets are owned whole; `managed` targets and
+/// shared MCP configs record just AgentDock's part, and `append` targets
+/// stay unowned because they carry the user's own content.
+fn record_ownership(ledger: &mut OwnershipLedger, item: &SyncItem, now: u128) {
+    let (hash, managed_keys, mcp_servers) = match &item.ownership {
+        Ownership::Unowned => return,
+        Ownership::Released => {
+            ledger.entries.remove(&item.target_relative_path);
+            return;
+        }
+        Ownership::File => (Some(content_hash(item.after.as_bytes())), None, None),
+        Ownership::Managed(keys) => (None, Some(keys.clone()), None),
+        Ownership::Mcp(servers) => (None, None, Some(servers.clone())),
+    };
+    ledger.entries.insert(
+        item.target_relative_path.clone(),
+        LedgerEntry {
+            category: item.category.clone(),
+            source_file: item.source_file.clone(),
+            updated_at: now,
+            hash,
+            mcp_servers,
+            managed: managed_keys.is_some(),
+            managed_keys: managed_keys.unwrap_or_default(),
+        },
+    );
+}
+
 pub fn preview_sync_inner() -> Result<SyncPreview, String> {
-    use crate::workspace::ensure_workspace_layout;
+    use crate::agents::load_agent_registry;
     use crate::mapping::load_mapping;
+    use crate::workspace::ensure_workspace_layout;
 
     ensure_workspace_layout()?;
     let mapping = load_mapping()?;
-    let items = build_sync_items(&mapping)?;
+    let registry = load_agent_registry()?;
+    let items = build_sync_items(&mapping, &registry)?;
     Ok(SyncPreview {
         generated_at: now_millis()?,
         items,
     })
 }
 
+/// Full `before`/`after` of one item of the current sync preview.
+pub fn sync_item_content_inner(id: &str) -> Result<SyncItemContent, String> {
+    use crate::agents::load_agent_registry;
+    use crate::mapping::load_mapping;
+    use crate::workspace::ensure_workspace_layout;
+
+    ensure_workspace_layout()?;
+    let mapping = load_mapping()?;
+    let registry = load_agent_registry()?;
+    let item = build_sync_items(&mapping, &registry)?
+        .into_iter()
+        .find(|item| item.id == id)
+        .ok_or_else(|| format!("Unknown sync item: {id}"))?;
+    Ok(SyncItemContent {
+        id: item.id,
+        before: item.before,
+        after: item.after,
+    })
+}
+
+/// Applies the selected items (all pending ones if none are selected). With
+/// `expected_hashes` from the preview, refuses to apply anything that changed
+/// since.
 pub fn apply_sync_inner(
     selected_ids: Vec<String>,
     source_prompt_snapshots: Option<Vec<SourcePromptSnapshot>>,
+    conflict_resolutions: Option<HashMap<String, ConflictResolution>>,
+    expected_hashes: Option<HashMap<String, SyncItemHashes>>,
 ) -> Result<ApplySyncResult, String> {
-    use crate::workspace::ensure_workspace_layout;
+    use crate::agents::load_agent_registry;
     use crate::mapping::load_mapping;
+    use crate::workspace::ensure_workspace_layout;
 
     ensure_workspace_layout()?;
     let mapping = load_mapping()?;
-    let all_items = build_sync_items(&mapping)?;
+    let registry = load_agent_registry()?;
+    let all_items = build_sync_items(&mapping, &registry)?;
 
     let selected: HashSet<String> = selected_ids.into_iter().collect();
     let should_filter = !selected.is_empty();
 
+    if let Some(failed) = all_items
+        .iter()
+        .find(|item| item.status == "error" && selected.contains(&item.id))
+    {
+        return Err(format!(
+            "Cannot apply {}: {}",
+            failed.id,
+            failed.error.as_deref().unwrap_or("render failed")
+        ));
+    }
+
+    if let Some(expected) = &expected_hashes {
+        let stale = stale_item_ids(&all_items, &selected, expected);
+        if !stale.is_empty() {
+            return Err(format!(
+                "Stale preview: these items changed since the preview, preview again before applying: {}",
+                stale.join(", ")
+            ));
+        }
+    }
+
     let chosen: Vec<SyncItem> = all_items
         .into_iter()
-        .filter(|item| item.status != "unchanged")
+        .filter(|item| item.status != "unchanged" && item.status != "error")
         .filter(|item| !should_filter || selected.contains(&item.id))
         .collect();
 
-    if chosen.is_empty() {
+    let resolutions = conflict_resolutions.unwrap_or_default();
+    let unresolved: Vec<&str> = chosen
+        .iter()
+        .filter(|item| item.conflict.is_some() && !resolutions.contains_key(&item.id))
+        .map(|item| item.id.as_str())
+        .collect();
+    if !unresolved.is_empty() {
+        return Err(format!(
+            "Targets were edited outside AgentDock since the last sync; choose overwrite, skip or pull for: {}",
+            unresolved.join(", ")
+        ));
+    }
+
+    let mut writes = Vec::new();
+    let mut pulls = Vec::new();
+    for mut item in chosen {
+        let conflict = match item.conflict.take() {
+            Some(conflict) => conflict,
+            None => {
+                writes.push(item);
+                continue;
+            }
+        };
+        match resolutions.get(&item.id) {
+            Some(ConflictResolution::Overwrite) => {
+                item.status = conflict.planned_status;
+                writes.push(item);
+            }
+            Some(ConflictResolution::Pull) => match (conflict.pull_target, conflict.pull_content) {
+                (Some(target), Some(content)) => pulls.push((item, PullPlan { target, content })),
+                _ => return Err(format!("Cannot pull {} into source", item.id)),
+            },
+            Some(ConflictResolution::Skip) | None => {}
+        }
+    }
+    let chosen = writes;
+
+    if chosen.is_empty() && pulls.is_empty() {
         return Ok(ApplySyncResult {
             backup_id: None,
             applied_count: 0,
             files: Vec::new(),
+            failure: None,
         });
     }
 
-    let backup_id = now_millis()?.to_string();
-    let backup_dir = backups_root()?.join(&backup_id);
-    fs::create_dir_all(&backup_dir).map_err(|e| e.to_string())?;
+    let src_root = source_root()?;
+    let mut targets: Vec<JournalTarget> = chosen
+        .iter()
+        .map(|item| JournalTarget {
+            agent: item.agent.clone(),
+            relative: item.target_relative_path.clone(),
+            absolute: PathBuf::from(&item.target_absolute_path),
+        })
+        .collect();
+    let mut pulled_sources = HashSet::new();
+    for (_, plan) in &pulls {
+        let source_rel = normalize_relative_path(&plan.target)?;
+        let source_rel_str = to_slash_path(&source_rel);
+        if !pulled_sources.insert(source_rel_str.clone()) {
+            return Err(format!(
+                "Multiple conflicts pull into the same source file: {source_rel_str}"
+            ));
+        }
+        targets.push(JournalTarget {
+            agent: "source".to_string(),
+            relative: source_rel_str,
+            absolute: src_root.join(&source_rel),
+        });
+    }
 
-    let mut entries = Vec::new();
-    let mut applied_files = Vec::new();
+    let mut transaction = ApplyTransaction::begin("sync", &targets)?;
+    let mut ledgers: BTreeMap<String, OwnershipLedger> = BTreeMap::new();
+    let applied_at = now_millis()?;
+    let outcome = write_sync_changes(
+        &mut transaction,
+        &registry,
+        &chosen,
+        &pulls,
+        &targets[chosen.len()..],
+        &mut ledgers,
+        applied_at,
+    )
+    .and_then(|applied_files| {
+        let mut snapshots = Vec::new();
+        snapshot_source_prompt_files(
+            &registry,
+            &pulled_sources,
+            &mut snapshots,
+            source_prompt_snapshots,
+        )?;
+        save_ledgers(&mut ledgers, &registry)?;
+        Ok((applied_files, snapshots))
+    });
 
-    for item in &chosen {
-        let target_abs = PathBuf::from(&item.target_absolute_path);
-        let existed_before = target_abs.exists();
-
-        if existed_before {
-            let backup_file = backup_dir
-                .join(&item.agent)
-                .join(Path::new(&item.target_relative_path));
-            let original = fs::read(&target_abs).map_err(|e| e.to_string())?;
-            write_atomic_bytes(&backup_file, &original)?;
+    match outcome {
+        Ok((applied_files, snapshots)) => {
+            let backup_id = transaction.commit_and_prune(snapshots)?;
+            Ok(ApplySyncResult {
+                backup_id: Some(backup_id),
+                applied_count: applied_files.len(),
+                files: applied_files,
+                failure: None,
+            })
         }
+        Err(error) => Ok(transaction.rollback(error)),
+    }
+}
 
-        entries.push(BackupEntry {
+/// The items a sync of `mapping`, which no longer lists `category`, would
+/// emit to remove what AgentDock wrote for that category.
+pub fn category_orphan_items(
+    mapping: &MappingConfig,
+    registry: &AgentRegistry,
+    category: &str,
+) -> Result<Vec<SyncItem>, String> {
+    Ok(build_sync_items(mapping, registry)?
+        .into_iter()
+        .filter(|item| item.category == category)
+        .filter(|item| matches!(item.ownership, Ownership::Released))
+        .collect())
+}
+
+/// Applies orphan `items` in one transaction (trigger `trigger`) and runs
+/// `finish` before committing it; if either fails, the targets are rolled
+/// back.
+pub fn remove_orphan_items(
+    trigger: &str,
+    registry: &AgentRegistry,
+    items: &[SyncItem],
+    finish: impl FnOnce() -> Result<(), String>,
+) -> Result<ApplySyncResult, String> {
+    let targets: Vec<JournalTarget> = items
+        .iter()
+        .map(|item| JournalTarget {
             agent: item.agent.clone(),
-            target_relative_path: item.target_relative_path.clone(),
-            target_absolute_path: item.target_absolute_path.clone(),
-            existed_before,
-        });
+            relative: item.target_relative_path.clone(),
+            absolute: PathBuf::from(&item.target_absolute_path),
+        })
+        .collect();
+    let mut transaction = ApplyTransaction::begin(trigger, &targets)?;
+    let mut ledgers: BTreeMap<String, OwnershipLedger> = BTreeMap::new();
+    let applied_at = now_millis()?;
+    let outcome = write_sync_changes(
+        &mut transaction,
+        registry,
+        items,
+        &[],
+        &[],
+        &mut ledgers,
+        applied_at,
+    )
+    .and_then(|applied_files| {
+        finish()?;
+        save_ledgers(&mut ledgers, registry)?;
+        Ok(applied_files)
+    });
 
-        write_atomic_bytes(&target_abs, item.after.as_bytes())?;
+    match outcome {
+        Ok(applied_files) => Ok(ApplySyncResult {
+            backup_id: Some(transaction.commit(Vec::new())?),
+            applied_count: applied_files.len(),
+            files: applied_files,
+            failure: None,
+        }),
+        Err(error) => Ok(transaction.rollback(error)),
+    }
+}
+
+/// Performs the planned writes, deletes and pulls in journal order.
+/// `pull_targets` are the source files `pulls` write to.
+fn write_sync_changes(
+    transaction: &mut ApplyTransaction,
+    registry: &AgentRegistry,
+    writes: &[SyncItem],
+    pulls: &[(SyncItem, PullPlan)],
+    pull_targets: &[JournalTarget],
+    ledgers: &mut BTreeMap<String, OwnershipLedger>,
+    applied_at: u128,
+) -> Result<Vec<String>, String> {
+    let mut applied_files = Vec::new();
+    for item in writes {
+        let target_abs = PathBuf::from(&item.target_absolute_path);
+        if item.status == "delete" {
+            let agent_root = registry
+                .find(&item.agent)
+                .ok_or_else(|| format!("Unsupported agent: {}", item.agent))?
+                .root_path()?;
+            transaction.step(|| remove_file_and_empty_parents(&target_abs, &agent_root))?;
+        } else {
+            transaction.step(|| write_atomic_bytes(&target_abs, item.after.as_bytes()))?;
+        }
         applied_files.push(item.target_absolute_path.clone());
+        record_ownership(ledger_for(ledgers, &item.agent)?, item, applied_at);
     }
-    snapshot_source_prompt_files(&backup_dir, &mut entries, source_prompt_snapshots)?;
 
-    let manifest = BackupManifest {
-        backup_id: backup_id.clone(),
-        created_at: now_millis()?,
-        trigger: "sync".to_string(),
-        entries,
-    };
-    let payload = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
-    write_atomic_bytes(&backup_dir.join("manifest.json"), payload.as_bytes())?;
+    for ((item, plan), source) in pulls.iter().zip(pull_targets) {
+        transaction.step(|| write_atomic_bytes(&source.absolute, plan.content.as_bytes()))?;
+        applied_files.push(source.absolute.display().to_string());
 
-    Ok(ApplySyncResult {
-        backup_id: Some(backup_id),
-        applied_count: applied_files.len(),
-        files: applied_files,
-    })
+        // The target is now what source says, so adopt its hash as the baseline.
+        let target_bytes = fs::read(&item.target_absolute_path).map_err(|e| e.to_string())?;
+        ledger_for(ledgers, &item.agent)?.adopt_hash(
+            &item.target_relative_path,
+            content_hash(&target_bytes),
+            applied_at,
+        );
+    }
+    Ok(applied_files)
 }
 
+/// Backs up the instruction prompt files not already changed by this apply
+/// (`changed_sources`) into `entries`.
 fn snapshot_source_prompt_files(
-    backup_dir: &Path,
+    registry: &AgentRegistry,
+    changed_sources: &HashSet<String>,
     entries: &mut Vec<BackupEntry>,
     source_prompt_snapshots: Option<Vec<SourcePromptSnapshot>>,
 ) -> Result<(), String> {
     let src_root = source_root()?;
+    let prompt_files = source_prompt_files(registry);
     let mut snapshots_by_relative: HashMap<String, SourcePromptSnapshot> = HashMap::new();
     if let Some(snapshots) = source_prompt_snapshots {
         for snapshot in snapshots {
-            if SOURCE_PROMPT_FILES.contains(&snapshot.relative_path.as_str()) {
+            if prompt_files.contains(&snapshot.relative_path) {
                 snapshots_by_relative.insert(snapshot.relative_path.clone(), snapshot);
             }
         }
     }
 
-    for relative in SOURCE_PROMPT_FILES {
-        if entries
-            .iter()
-            .any(|entry| entry.agent == "source" && entry.target_relative_path == relative)
-        {
+    for relative in &prompt_files {
+        if changed_sources.contains(relative) {
             continue;
         }
 
         let target_abs = src_root.join(relative);
         let snapshot = snapshots_by_relative.remove(relative);
-        let (existed_before, backup_bytes) = if let Some(snapshot) = snapshot {
-            if snapshot.existed_before {
-                (true, Some(snapshot.content.into_bytes()))
-            } else {
-                (false, None)
-            }
+        let backup_bytes = if let Some(snapshot) = snapshot {
+            snapshot
+                .existed_before
+                .then(|| snapshot.content.into_bytes())
         } else if target_abs.exists() {
-            (
-                true,
-                Some(fs::read(&target_abs).map_err(|e| e.to_string())?),
-            )
+            Some(fs::read(&target_abs).map_err(|e| e.to_string())?)
         } else {
-            (false, None)
+            None
         };
 
-        if let Some(original) = backup_bytes {
-            let backup_file = backup_dir.join("source").join(Path::new(relative));
-            write_atomic_bytes(&backup_file, &original)?;
-        }
-
-        entries.push(BackupEntry {
-            agent: "source".to_string(),
-            target_relative_path: relative.to_string(),
-            target_absolute_path: target_abs.display().to_string(),
-            existed_before,
-        });
+        entries.push(backup_entry(
+            "source",
+            relative,
+            &target_abs,
+            backup_bytes.as_deref(),
+        )?);
     }
     Ok(())
 }
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+
+use crate::agents::AgentDefinition;
+use crate::paths::vars_path;
+
+const IF_OPEN: &str = "{{#if ";
+const ELSE_TAG: &str = "{{else}}";
+const IF_CLOSE: &str = "{{/if}}";
+
+/// User-defined template values from `vars.json` (a flat string map).
+pub fn load_template_vars() -> Result<BTreeMap<String, String>, String> {
+    let path = vars_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let raw = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&raw).map_err(|e| format!("Invalid template vars {}: {e}", path.display()))
+}
+
+/// Values visible to templates rendered for `agent`. Built-in names take
+/// precedence over user-defined ones.
+pub fn template_context(
+    agent: &AgentDefinition,
+    vars: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut context = vars.clone();
+    context.insert("agent".to_string(), agent.id.clone());
+    context.insert("os".to_string(), std::env::consts::OS.to_string());
+    context.insert(
+        "hostname".to_string(),
+        gethostname::gethostname().to_string_lossy().into_owned(),
+    );
+    context
+}
+
+/// Evaluates `name`, `name == "value"` or `name != "value"`. Unknown names
+/// are empty.
+fn evaluate(condition: &str, context: &BTreeMap<String, String>) -> Result<bool, String> {
+    let lookup = |name: &str| -> Result<&str, String> {
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid template condition: {condition}"));
+        }
+        Ok(context.get(name).map_or("", String::as_str))
+    };
+    let literal = |value: &str| -> Result<String, String> {
+        serde_json::from_str::<String>(value.trim())
+            .map_err(|_| format!("Expected a quoted string in condition: {condition}"))
+    };
+
+    if let Some((name, value)) = condition.split_once("!=") {
+        return Ok(lookup(name)? != literal(value)?);
+    }
+    if let Some((name, value)) = condition.split_once("==") {
+        return Ok(lookup(name)? == literal(value)?);
+    }
+    let value = lookup(condition)?;
+    Ok(!value.is_empty() && value != "false")
+}
+
+/// Position of the `{{else}}` and `{{/if}}` closing the block whose body
+/// starts at `text`, skipping nested blocks.
+fn find_block_end(text: &str) -> Result<(Option<usize>, usize), String> {
+    let mut depth = 0usize;
+    let mut else_at = None;
+    let mut cursor = 0;
+    while let Some(offset) = text[cursor..].find("{{") {
+        let at = cursor + offset;
+        let rest = &text[at..];
+        if rest.starts_with(IF_OPEN) {
+            depth += 1;
+        } else if rest.starts_with(IF_CLOSE) {
+            if depth == 0 {
+                return Ok((else_at, at));
+            }
+            depth -= 1;
+        } else if rest.starts_with(ELSE_TAG) && depth == 0 {
+            if else_at.is_some() {
+                return Err("Template block has more than one {{else}}".to_string());
+            }
+            else_at = Some(at);
+        }
+        cursor = at + 2;
+    }
+    Err("Template block is missing {{/if}}".to_string())
+}
+
+/// Drops the line break after a block tag that sits on its own line (`before`
+/// is the text leading up to the tag), so conditionals leave no blank lines.
+fn skip_tag_newline<'a>(before: &str, after: &'a str) -> &'a str {
+    if !before.is_empty() && !before.ends_with('\n') {
+        return after;
+    }
+    after
+        .strip_prefix("\r\n")
+        .or_else(|| after.strip_prefix('\n'))
+        .unwrap_or(after)
+}
+
+/// Renders `{{name}}` variables and `{{#if ...}}…{{else}}…{{/if}}` blocks.
+/// Unknown variables are left untouched so literal `{{...}}` text, such as
+/// Gemini's `{{args}}`, survives.
+pub fn render_template(text: &str, context: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let tag = &rest[start..];
+
+        if let Some(after_open) = tag.strip_prefix(IF_OPEN) {
+            let close = after_open
+                .find("}}")
+                .ok_or_else(|| "Unterminated {{#if}} tag".to_string())?;
+            let condition = &after_open[..close];
+            let body = skip_tag_newline(&output, &after_open[close + 2..]);
+            let (else_at, end_at) = find_block_end(body)?;
+            let (then_part, else_part) = match else_at {
+                Some(at) => (
+                    &body[..at],
+                    skip_tag_newline(&body[..at], &body[at + ELSE_TAG.len()..end_at]),
+                ),
+                None => (&body[..end_at], ""),
+            };
+            let chosen = if evaluate(condition, context)? {
+                then_part
+            } else {
+                else_part
+            };
+            output.push_str(&render_template(chosen, context)?);
+            rest = skip_tag_newline(&body[..end_at], &body[end_at + IF_CLOSE.len()..]);
+            continue;
+        }
+        if tag.starts_with(IF_CLOSE) || tag.starts_with(ELSE_TAG) {
+            return Err(format!(
+                "Unexpected {} without a matching {{{{#if}}}}",
+                &tag[..tag.find("}}").map_or(tag.len(), |i| i + 2)]
+            ));
+        }
+
+        match tag[2..].find("}}") {
+            Some(close) => {
+                let name = tag[2..2 + close].trim();
+                match context.get(name) {
+                    Some(value) => output.push_str(value),
+                    None => output.push_str(&tag[..close + 4]),
+                }
+                rest = &tag[close + 4..];
+            }
+            None => {
+                output.push_str(tag);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Whether rendering changes `text`. Pulling a rendered target back into such
+/// a source would flatten its variables and conditionals.
+pub fn is_templated(text: &str, context: &BTreeMap<String, String>) -> bool {
+    render_template(text, context).map_or(true, |rendered| rendered != text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renders_known_variables_and_keeps_unknown_ones() {
+        let context = context(&[("agent", "codex")]);
+        assert_eq!(
+            render_template("for {{ agent }}: {{args}}", &context).unwrap(),
+            "for codex: {{args}}"
+        );
+        assert_eq!(
+            render_template("open {{agent", &context).unwrap(),
+            "open {{agent"
+        );
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let context = context(&[("agent", "codex"), ("work", "false"), ("team", "x")]);
+        let render = |text: &str| render_template(text, &context).unwrap();
+        assert_eq!(render(r#"{{#if agent == "codex"}}yes{{/if}}"#), "yes");
+        assert_eq!(
+            render(r#"{{#if agent != "codex"}}yes{{else}}no{{/if}}"#),
+            "no"
+        );
+        assert_eq!(render("{{#if team}}a{{/if}}{{#if work}}b{{/if}}"), "a");
+        assert_eq!(render("{{#if missing}}a{{else}}b{{/if}}"), "b");
+    }
+
+    #[test]
+    fn renders_nested_blocks() {
+        let context = context(&[("agent", "claude"), ("work", "true")]);
+        let text = concat!(
+            "{{#if work}}\n",
+            "work\n",
+            "{{#if agent == \"codex\"}}\n",
+            "codex\n",
+            "{{else}}\n",
+            "other\n",
+            "{{/if}}\n",
+            "{{else}}\n",
+            "home\n",
+            "{{/if}}\n",
+            "end\n",
+        );
+        assert_eq!(
+            render_template(text, &context).unwrap(),
+            "work\nother\nend\n"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let context = context(&[("agent", "codex")]);
+        let error = |text: &str| render_template(text, &context).unwrap_err();
+        assert!(error("{{#if agent").contains("Unterminated"));
+        assert!(error("{{#if agent}}open").contains("missing {{/if}}"));
+        assert!(error("{{#if agent}}{{#if agent}}x{{/if}}").contains("missing {{/if}}"));
+        assert!(error("{{#if agent}}a{{else}}b{{else}}c{{/if}}").contains("more than one"));
+        assert!(error("stray {{/if}}").contains("Unexpected {{/if}}"));
+        assert!(error("stray {{else}}").contains("Unexpected {{else}}"));
+        assert!(error("{{#if agent = \"codex\"}}x{{/if}}").contains("Invalid template condition"));
+        assert!(error("{{#if agent == codex}}x{{/if}}").contains("quoted string"));
+    }
+
+    #[test]
+    fn detects_templated_text() {
+        let context = context(&[("agent", "codex")]);
+        assert!(is_templated("{{agent}}", &context));
+        assert!(is_templated("{{#if agent}}", &context));
+        assert!(!is_templated("plain {{args}}", &context));
+    }
+}
@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use toml_edit::DocumentMut;
+
+use crate::agents::AgentDefinition;
+use crate::files::{read_text, to_slash_path};
+
+/// Rewrites one source file for an agent: returns the (possibly renamed)
+/// target path and the converted content.
+pub type RenderFn = fn(&AgentDefinition, PathBuf, String) -> Result<(PathBuf, String), String>;
+
+/// Maps an agent file (relative to the mapped directory) back to a source file
+/// relative to the category root, with the content to write there.
+pub type PullFn = fn(&Path, &str, &str, &[String]) -> Result<(String, String), String>;
+
+/// Per-category conversion between source files and an agent's native
+/// format. Only applied to directory mappings.
+pub struct CategoryTransformer {
+    pub render: RenderFn,
+    pub pull: PullFn,
+}
+
+pub fn category_transformer(category: &str) -> Option<CategoryTransformer> {
+    match category {
+        "commands" => Some(CategoryTransformer {
+            render: transform_command,
+            pull: pull_command,
+        }),
+        _ => None,
+    }
+}
+
+/// File format an agent reads slash commands in.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandFormat {
+    /// Markdown with YAML front matter and a `$ARGUMENTS` placeholder.
+    #[default]
+    Markdown,
+    /// TOML with `description` and `prompt` keys and an `{{args}}` placeholder.
+    Toml,
+}
+
+impl CommandFormat {
+    fn of(path: &Path) -> Option<CommandFormat> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("md") => Some(CommandFormat::Markdown),
+            Some("toml") => Some(CommandFormat::Toml),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            CommandFormat::Markdown => "md",
+            CommandFormat::Toml => "toml",
+        }
+    }
+}
+
+const ARGUMENTS_PLACEHOLDER: &str = "$ARGUMENTS";
+const TOML_ARGS_PLACEHOLDER: &str = "{{args}}";
+
+/// Canonical slash command. `front_matter` keeps the raw lines so keys other
+/// agents do not understand survive a Markdown round trip.
+struct Command {
+    front_matter: Vec<String>,
+    body: String,
+}
+
+impl Command {
+    fn description(&self) -> Option<String> {
+        self.front_matter.iter().find_map(|line| {
+            let value = line.strip_prefix("description:")?.trim();
+            let unquoted = serde_json::from_str::<String>(value).ok().or_else(|| {
+                value
+                    .strip_prefix('\'')
+                    .and_then(|v| v.strip_suffix('\''))
+                    .map(str::to_string)
+            });
+            Some(unquoted.unwrap_or_else(|| value.to_string()))
+        })
+    }
+
+    /// Replaces the `description:` line in place, leaving it untouched when
+    /// the value did not change.
+    fn set_description(&mut self, description: Option<String>) {
+        if self.description() == description {
+            return;
+        }
+        let position = self
+            .front_matter
+            .iter()
+            .position(|line| line.starts_with("description:"));
+        let line = description.map(|text| format!("description: {}", Value::from(text)));
+        match (position, line) {
+            (Some(index), Some(line)) => self.front_matter[index] = line,
+            (Some(index), None) => {
+                self.front_matter.remove(index);
+            }
+            (None, Some(line)) => self.front_matter.insert(0, line),
+            (None, None) => {}
+        }
+    }
+}
+
+fn parse_markdown_command(content: &str) -> Command {
+    let mut lines = content.split_inclusive('\n');
+    let opens = lines.next().is_some_and(|first| first.trim_end() == "---");
+    if opens {
+        let mut front_matter = Vec::new();
+        let mut consumed = content.find('\n').map_or(content.len(), |i| i + 1);
+        for line in lines {
+            consumed += line.len();
+            if line.trim_end() == "---" {
+                return Command {
+                    front_matter,
+                    body: content[consumed..].to_string(),
+                };
+            }
+            front_matter.push(line.trim_end().to_string());
+        }
+    }
+    Command {
+        front_matter: Vec::new(),
+        body: content.to_string(),
+    }
+}
+
+fn render_markdown_command(command: &Command) -> String {
+    if command.front_matter.is_empty() {
+        return command.body.clone();
+    }
+    format!(
+        "---\n{}\n---\n{}",
+        command.front_matter.join("\n"),
+        command.body
+    )
+}
+
+fn parse_toml_command(content: &str) -> Result<Command, String> {
+    let document = content
+        .parse::<DocumentMut>()
+        .map_err(|e| format!("Command is not valid TOML: {e}"))?;
+    let prompt = document
+        .get("prompt")
+        .and_then(|item| item.as_str())
+        .ok_or_else(|| "Command TOML must define a prompt string".to_string())?;
+    let mut command = Command {
+        front_matter: Vec::new(),
+        body: prompt.replace(TOML_ARGS_PLACEHOLDER, ARGUMENTS_PLACEHOLDER),
+    };
+    let description = document
+        .get("description")
+        .and_then(|item| item.as_str())
+        .map(str::to_string);
+    command.set_description(description);
+    Ok(command)
+}
+
+fn render_toml_command(command: &Command) -> String {
+    let mut document = DocumentMut::new();
+    if let Some(description) = command.description() {
+        document["description"] = toml_edit::value(description);
+    }
+    document["prompt"] = toml_edit::value(
+        command
+            .body
+            .replace(ARGUMENTS_PLACEHOLDER, TOML_ARGS_PLACEHOLDER),
+    );
+    document.to_string()
+}
+
+fn parse_command(content: &str, format: CommandFormat) -> Result<Command, String> {
+    match format {
+        CommandFormat::Markdown => Ok(parse_markdown_command(content)),
+        CommandFormat::Toml => parse_toml_command(content),
+    }
+}
+
+fn render_command(command: &Command, format: CommandFormat) -> String {
+    match format {
+        CommandFormat::Markdown => render_markdown_command(command),
+        CommandFormat::Toml => render_toml_command(command),
+    }
+}
+
+/// Converts a command file into the agent's command format and rewrites the
+/// extension to match. Files that are neither Markdown nor TOML pass through.
+fn transform_command(
+    agent: &AgentDefinition,
+    target: PathBuf,
+    content: String,
+) -> Result<(PathBuf, String), String> {
+    let wanted = agent.command_format.unwrap_or_default();
+    match CommandFormat::of(&target) {
+        Some(format) if format != wanted => {
+            let command = parse_command(&content, format)?;
+            Ok((
+                target.with_extension(wanted.extension()),
+                render_command(&command, wanted),
+            ))
+        }
+        _ => Ok((target, content)),
+    }
+}
+
+/// Maps an agent's command file back onto source: the source file with the
+/// same stem if one exists (keeping its front matter), otherwise a Markdown
+/// file. Paths are relative to the category.
+fn pull_command(
+    category_root: &Path,
+    agent_relative: &str,
+    content: &str,
+    source_files: &[String],
+) -> Result<(String, String), String> {
+    let agent_path = Path::new(agent_relative);
+    let Some(agent_format) = CommandFormat::of(agent_path) else {
+        return Ok((agent_relative.to_string(), content.to_string()));
+    };
+    let counterpart = source_files.iter().find(|file| {
+        let path = Path::new(file.as_str());
+        path.with_extension("") == agent_path.with_extension("")
+            && CommandFormat::of(path).is_some()
+    });
+    let source_relative = match counterpart {
+        Some(file) => file.clone(),
+        None => to_slash_path(&agent_path.with_extension(CommandFormat::Markdown.extension())),
+    };
+    let source_format = CommandFormat::of(Path::new(&source_relative)).unwrap_or_default();
+    if source_format == agent_format {
+        return Ok((source_relative, content.to_string()));
+    }
+
+    let pulled = parse_command(content, agent_format)?;
+    let mut command = match counterpart {
+        Some(file) => parse_command(&read_text(&category_root.join(file))?, source_format)?,
+        None => Command {
+            front_matter: Vec::new(),
+            body: String::new(),
+        },
+    };
+    command.set_description(pulled.description());
+    command.body = pulled.body;
+    Ok((source_relative, render_command(&command, source_format)))
+}
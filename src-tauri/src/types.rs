@@ -1,4 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::agents::AgentDefinition;
+use crate::ledger::Ownership;
+use crate::mapping::SyncMode;
 
 #[derive(Debug, Serialize)]
 pub struct ScopeInfo {
@@ -11,8 +16,10 @@ pub struct WorkspaceInfo {
     pub app_root: String,
     pub source_root: String,
     pub mapping_path: String,
+    pub agents_path: String,
     pub categories: Vec<String>,
     pub scopes: Vec<ScopeInfo>,
+    pub agents: Vec<AgentDefinition>,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,6 +37,30 @@ pub struct FileContent {
     pub content: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictResolution {
+    /// Write the planned content over the edited target.
+    Overwrite,
+    /// Leave the target untouched for this sync.
+    Skip,
+    /// Copy the edited target back into its source file.
+    Pull,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SyncConflict {
+    /// Status the item would have without drift (`update`, `append`, `delete`).
+    pub planned_status: String,
+    pub recorded_hash: String,
+    pub current_hash: String,
+    /// Source file a `pull` resolution writes into, when one is possible.
+    pub pull_target: Option<String>,
+    pub resolutions: Vec<ConflictResolution>,
+    #[serde(skip)]
+    pub pull_content: Option<String>,
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct SyncItem {
     pub id: String,
@@ -39,8 +70,46 @@ pub struct SyncItem {
     pub target_relative_path: String,
     pub target_absolute_path: String,
     pub status: String,
+    /// Full contents are left out of previews; fetch them per item instead.
+    #[serde(skip)]
     pub before: String,
+    #[serde(skip)]
     pub after: String,
+    /// SHA-256 of `before` and `after`; sent back on apply to detect that the
+    /// preview went stale.
+    pub before_hash: String,
+    pub after_hash: String,
+    /// Unified diff of `before` → `after`, empty when they are equal.
+    pub diff: String,
+    pub diff_stats: DiffStats,
+    pub conflict: Option<SyncConflict>,
+    /// Why the item could not be rendered; set when `status` is `error`.
+    pub error: Option<String>,
+    /// How applying the item changes the ownership ledger.
+    #[serde(skip)]
+    pub ownership: Ownership,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct DiffStats {
+    pub added: usize,
+    pub removed: usize,
+    pub hunks: usize,
+}
+
+/// Full contents of one previewed item.
+#[derive(Debug, Serialize)]
+pub struct SyncItemContent {
+    pub id: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// The hashes a previewed item had, as passed back to `apply_sync`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SyncItemHashes {
+    pub before_hash: String,
+    pub after_hash: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -49,11 +118,42 @@ pub struct SyncPreview {
     pub items: Vec<SyncItem>,
 }
 
+/// An agent-side target that a pull cannot map back to source.
+#[derive(Debug, Serialize)]
+pub struct PullSkip {
+    pub agent: String,
+    pub category: String,
+    pub target_relative_path: String,
+    pub reason: String,
+}
+
+/// Pull items read an agent file (`source_file`, relative to the agent root)
+/// and write it into source (`target_*`).
+#[derive(Debug, Serialize)]
+pub struct PullPreview {
+    pub generated_at: u128,
+    pub items: Vec<SyncItem>,
+    pub skipped: Vec<PullSkip>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ApplySyncResult {
     pub backup_id: Option<String>,
     pub applied_count: usize,
     pub files: Vec<String>,
+    /// Set when a write failed and the apply was rolled back.
+    pub failure: Option<ApplyFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApplyFailure {
+    /// The file whose change failed, if the failure was tied to one.
+    pub path: Option<String>,
+    pub error: String,
+    /// Files put back to their state before the apply.
+    pub rolled_back: Vec<String>,
+    /// Files that could not be put back; the backup is kept for them.
+    pub rollback_errors: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,35 +169,150 @@ pub struct BackupEntry {
     pub target_relative_path: String,
     pub target_absolute_path: String,
     pub existed_before: bool,
+    /// Name of the backed-up content in the blob store: its SHA-256, or that
+    /// of its ciphertext while backups are encrypted. Absent for files that
+    /// did not exist and for copies kept inside older backup directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    /// Size and SHA-256 of the backed-up content, checked before it is
+    /// restored. Absent for files that did not exist and in older backups;
+    /// encrypted backups leave the hash out, so it cannot confirm a guess of
+    /// the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BackupManifest {
+    /// Manifest format; 0 for backups written before it was recorded.
+    #[serde(default)]
+    pub version: u32,
+    /// AgentDock version that wrote the backup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
     pub backup_id: String,
     pub created_at: u128,
     pub trigger: String,
     pub entries: Vec<BackupEntry>,
+    /// Pinned backups are never pruned by the retention policy.
+    #[serde(default)]
+    pub pinned: bool,
+    /// For `restore` backups: the backup that was restored over these files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restored_from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Clone)]
 pub struct BackupInfo {
     pub backup_id: String,
     pub created_at: u128,
     pub trigger: String,
     pub entry_count: usize,
+    pub pinned: bool,
+    pub restored_from: Option<String>,
+    pub label: Option<String>,
+    pub note: Option<String>,
+    pub tags: Vec<String>,
+    /// Size of the backed-up files.
+    pub total_bytes: u64,
+    /// What their stored copies take on disk after compression. Blobs shared
+    /// with other backups count towards each of them.
+    pub stored_bytes: u64,
+}
+
+/// A backup entry whose stored content is `missing`, `truncated` or
+/// `modified`.
+#[derive(Debug, Serialize)]
+pub struct BackupIssue {
+    pub agent: String,
+    pub target_relative_path: String,
+    pub problem: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupVerification {
+    pub backup_id: String,
+    pub ok: bool,
+    /// Entries whose content was checked.
+    pub checked: usize,
+    pub issues: Vec<BackupIssue>,
+}
+
+/// Narrows a backup search; every field that is set must match.
+#[derive(Debug, Deserialize, Default)]
+pub struct BackupQuery {
+    pub trigger: Option<String>,
+    /// Case-insensitive substring of the label or note.
+    pub label: Option<String>,
+    pub tag: Option<String>,
+    /// Backups with an entry of this agent (or `source`).
+    pub agent: Option<String>,
+    /// Backups with an entry whose path contains this.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneResult {
+    pub dry_run: bool,
+    /// Backups removed, or that would be removed on a dry run.
+    pub pruned: Vec<BackupInfo>,
+    pub kept: Vec<BackupInfo>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct RestoredSourcePrompts {
     pub base: String,
-    pub codex: String,
-    pub gemini: String,
-    pub claude: String,
+    /// Per-agent prompt content keyed by agent id.
+    pub agents: BTreeMap<String, String>,
+}
+
+/// Limits a restore to matching entries. Empty lists match everything;
+/// `paths` match a backed-up path or any path under it.
+#[derive(Debug, Deserialize, Default)]
+pub struct RestoreFilter {
+    #[serde(default)]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+}
+
+impl RestoreFilter {
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty() && self.paths.is_empty() && self.categories.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestorePlanEntry {
+    pub agent: String,
+    pub target_relative_path: String,
+    pub target_absolute_path: String,
+    pub category: Option<String>,
+    /// `write`, `delete` or `none`.
+    pub action: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestorePreview {
+    pub backup_id: String,
+    pub entries: Vec<RestorePlanEntry>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct RestoreResult {
     pub restored_count: usize,
+    /// Backup of the files as they were before the restore.
+    pub backup_id: Option<String>,
     pub source_prompts: RestoredSourcePrompts,
 }
 
@@ -108,6 +323,9 @@ pub struct BackupDetailEntry {
     pub existed_before: bool,
     pub backup_content: Option<String>,
     pub current_content: Option<String>,
+    /// Why the stored copy could not be shown as recorded: `missing`,
+    /// `truncated`, `modified` or a read error.
+    pub problem: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -117,3 +335,68 @@ pub struct BackupDetail {
     pub trigger: String,
     pub entries: Vec<BackupDetailEntry>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct BackupDiffEntry {
+    pub agent: String,
+    pub target_relative_path: String,
+    /// `added`, `removed` or `modified`, going from `from` to `to`, or
+    /// `unrecorded` when only one of two backups recorded the file.
+    pub status: String,
+    pub diff: String,
+    pub diff_stats: DiffStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupDiff {
+    pub from: String,
+    /// `None` when compared with what a sync would produce now.
+    pub to: Option<String>,
+    /// Files that differ; identical ones are left out.
+    pub entries: Vec<BackupDiffEntry>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MappingFieldError {
+    /// `None` for mapping-wide fields such as `version`.
+    pub category: Option<String>,
+    pub agent: Option<String>,
+    /// `version`, `category` or `target`.
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MappingTargetChange {
+    pub category: String,
+    pub agent: String,
+    pub before_target: Option<String>,
+    pub after_target: Option<String>,
+    pub before_sync_mode: Option<SyncMode>,
+    pub after_sync_mode: Option<SyncMode>,
+    pub before_templates: Option<bool>,
+    pub after_templates: Option<bool>,
+}
+
+/// A target file the next sync would change differently under a proposed
+/// mapping than under the current one.
+#[derive(Debug, Serialize)]
+pub struct MappingFileChange {
+    pub agent: String,
+    pub category: String,
+    pub target_relative_path: String,
+    /// The sync status under the proposed mapping: `create`, `update`,
+    /// `append`, `delete` or `error`.
+    pub status: String,
+    /// AgentDock wrote the file for a target the proposed mapping drops.
+    pub orphaned: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MappingValidation {
+    pub valid: bool,
+    pub errors: Vec<MappingFieldError>,
+    pub changes: Vec<MappingTargetChange>,
+    /// Empty while the proposed mapping is invalid.
+    pub files: Vec<MappingFileChange>,
+}
@@ -0,0 +1,54 @@
+use crate::backup::{list_backup_manifests, load_backup_manifest};
+use crate::blobs::inspect_backup_entry;
+use crate::encryption::{unlock_backup_key, BackupKey};
+use crate::paths::backups_root;
+use crate::types::{BackupIssue, BackupManifest, BackupVerification};
+use crate::workspace::ensure_workspace_layout;
+
+fn verify_manifest(
+    manifest: &BackupManifest,
+    key: Option<&BackupKey>,
+) -> Result<BackupVerification, String> {
+    let backup_dir = backups_root()?.join(&manifest.backup_id);
+    let mut checked = 0;
+    let mut issues = Vec::new();
+    for entry in manifest.entries.iter().filter(|entry| entry.existed_before) {
+        checked += 1;
+        if let (_, Some(problem)) = inspect_backup_entry(&backup_dir, entry, key)? {
+            issues.push(BackupIssue {
+                agent: entry.agent.clone(),
+                target_relative_path: entry.target_relative_path.clone(),
+                problem: problem.to_string(),
+            });
+        }
+    }
+    Ok(BackupVerification {
+        backup_id: manifest.backup_id.clone(),
+        ok: issues.is_empty(),
+        checked,
+        issues,
+    })
+}
+
+/// Checks every stored file of a backup against its recorded size and hash.
+pub fn verify_backup_inner(
+    backup_id: &str,
+    passphrase: Option<&str>,
+) -> Result<BackupVerification, String> {
+    ensure_workspace_layout()?;
+    let key = unlock_backup_key(passphrase)?;
+    let (_, manifest) =
+        load_backup_manifest(backup_id).map_err(|_| format!("Backup not found: {backup_id}"))?;
+    verify_manifest(&manifest, key.as_ref())
+}
+
+/// Verifies every backup, newest first.
+pub fn verify_all_backups_inner(
+    passphrase: Option<&str>,
+) -> Result<Vec<BackupVerification>, String> {
+    let key = unlock_backup_key(passphrase)?;
+    list_backup_manifests()?
+        .iter()
+        .map(|manifest| verify_manifest(manifest, key.as_ref()))
+        .collect()
+}
@@ -1,10 +1,16 @@
 use std::fs;
 
+use crate::agents::load_agent_registry;
+use crate::blobs::{migrate_legacy_backups, seal_blobs};
 use crate::files::list_files_recursive;
+use crate::journal::recover_interrupted_applies;
 use crate::mapping::{
-    bootstrap_source_from_agents, default_mapping, load_mapping, save_mapping_inner, CATEGORY_NAMES,
+    bootstrap_source_from_agents, category_names, default_mapping, load_mapping,
+    save_mapping_inner, CATEGORY_NAMES,
+};
+use crate::paths::{
+    agents_path, app_root, backups_root, mapping_path, resolve_scope_base, source_root,
 };
-use crate::paths::{app_root, backups_root, mapping_path, resolve_scope_base, source_root};
 use crate::types::{ScopeInfo, WorkspaceInfo};
 
 pub fn ensure_workspace_layout() -> Result<(), String> {
@@ -19,9 +25,10 @@ pub fn ensure_workspace_layout() -> Result<(), String> {
         fs::create_dir_all(source.join(category)).map_err(|e| e.to_string())?;
     }
 
+    let registry = load_agent_registry()?;
     let mapping = mapping_path()?;
     if !mapping.exists() {
-        let default = default_mapping();
+        let default = default_mapping(&registry);
         save_mapping_inner(&default)?;
     }
 
@@ -36,39 +43,40 @@ pub fn ensure_scope_dir(scope: &str) -> Result<std::path::PathBuf, String> {
 
 pub fn init_workspace_inner() -> Result<WorkspaceInfo, String> {
     ensure_workspace_layout()?;
+    recover_interrupted_applies()?;
+    migrate_legacy_backups()?;
+    seal_blobs()?;
 
     let mapping = load_mapping()?;
+    let source = source_root()?;
+    for category in mapping.categories.keys() {
+        fs::create_dir_all(source.join(category)).map_err(|e| e.to_string())?;
+    }
     bootstrap_source_from_agents(&mapping)?;
 
     // Check if source was bootstrapped and has files
-    let source = source_root()?;
     let _files = list_files_recursive(&source)?;
     let mapping_p = mapping_path()?;
+    let registry = load_agent_registry()?;
 
-    let scopes = vec![
-        ScopeInfo {
-            name: "source".to_string(),
-            path: source.display().to_string(),
-        },
-        ScopeInfo {
-            name: "codex".to_string(),
-            path: resolve_scope_base("codex")?.display().to_string(),
-        },
-        ScopeInfo {
-            name: "gemini".to_string(),
-            path: resolve_scope_base("gemini")?.display().to_string(),
-        },
-        ScopeInfo {
-            name: "claude".to_string(),
-            path: resolve_scope_base("claude")?.display().to_string(),
-        },
-    ];
+    let mut scopes = vec![ScopeInfo {
+        name: "source".to_string(),
+        path: source.display().to_string(),
+    }];
+    for agent in &registry.agents {
+        scopes.push(ScopeInfo {
+            name: agent.id.clone(),
+            path: agent.root_path()?.display().to_string(),
+        });
+    }
 
     Ok(WorkspaceInfo {
         app_root: app_root()?.display().to_string(),
         source_root: source.display().to_string(),
         mapping_path: mapping_p.display().to_string(),
-        categories: CATEGORY_NAMES.iter().map(|v| v.to_string()).collect(),
+        agents_path: agents_path()?.display().to_string(),
+        categories: category_names(&mapping),
         scopes,
+        agents: registry.agents,
     })
 }
@@ -1,13 +1,29 @@
 import { invoke } from "@tauri-apps/api/core";
 import type {
   ApplySyncResult,
+  BackupAnnotation,
   BackupDetail,
+  BackupDiff,
   BackupInfo,
+  BackupQuery,
+  BackupVerification,
+  CategoryMapping,
+  ConflictResolution,
   FileContent,
+  MappingConfig,
+  MappingValidation,
+  PullPreview,
+  PruneResult,
+  RestoreFilter,
+  RestorePreview,
   RestoreResult,
+  RetentionPolicy,
   ScopeFiles,
   ScopeName,
+  Settings,
   SourcePromptSnapshot,
+  SyncItemContent,
+  SyncItemHashes,
   SyncPreview,
   WorkspaceInfo,
 } from "./types";
@@ -42,22 +58,144 @@ export function previewSync() {
 export function applySync(
   selectedIds: string[],
   sourcePromptSnapshots?: SourcePromptSnapshot[],
+  conflictResolutions?: Record<string, ConflictResolution>,
+  expectedHashes?: Record<string, SyncItemHashes>,
 ) {
-  return invoke<ApplySyncResult>("apply_sync", { selectedIds, sourcePromptSnapshots });
+  return invoke<ApplySyncResult>("apply_sync", {
+    selectedIds,
+    sourcePromptSnapshots,
+    conflictResolutions,
+    expectedHashes,
+  });
+}
+
+export function getSyncItemContent(id: string) {
+  return invoke<SyncItemContent>("get_sync_item_content", { id });
+}
+
+export function previewPull(agent?: string, category?: string) {
+  return invoke<PullPreview>("preview_pull", { agent, category });
+}
+
+export function applyPull(selectedIds: string[], agent?: string, category?: string) {
+  return invoke<ApplySyncResult>("apply_pull", { agent, category, selectedIds });
+}
+
+export function getPullItemContent(id: string) {
+  return invoke<SyncItemContent>("get_pull_item_content", { id });
 }
 
 export function listBackups() {
   return invoke<BackupInfo[]>("list_backups");
 }
 
-export function restoreBackup(backupId: string) {
-  return invoke<RestoreResult>("restore_backup", { backupId });
+export function verifyBackup(backupId: string, passphrase?: string) {
+  return invoke<BackupVerification>("verify_backup", { backupId, passphrase });
+}
+
+export function verifyAllBackups(passphrase?: string) {
+  return invoke<BackupVerification[]>("verify_all_backups", { passphrase });
+}
+
+export function searchBackups(query: BackupQuery) {
+  return invoke<BackupInfo[]>("search_backups", { query });
+}
+
+export function createSnapshot(annotation: BackupAnnotation = {}) {
+  return invoke<BackupInfo>("create_snapshot", { ...annotation });
+}
+
+export function annotateBackup(backupId: string, annotation: BackupAnnotation) {
+  return invoke<BackupInfo>("annotate_backup", { backupId, ...annotation });
+}
+
+export function restoreBackup(backupId: string, filter?: RestoreFilter, passphrase?: string) {
+  return invoke<RestoreResult>("restore_backup", { backupId, filter, passphrase });
+}
+
+export function previewRestore(backupId: string, filter?: RestoreFilter, passphrase?: string) {
+  return invoke<RestorePreview>("preview_restore", { backupId, filter, passphrase });
 }
 
 export function deleteBackup(backupId: string) {
   return invoke<void>("delete_backup", { backupId });
 }
 
-export function getBackupDetail(backupId: string) {
-  return invoke<BackupDetail>("get_backup_detail", { backupId });
+export function getBackupDetail(backupId: string, passphrase?: string) {
+  return invoke<BackupDetail>("get_backup_detail", { backupId, passphrase });
+}
+
+/** Compares two backups, or `from` with a fresh sync when `to` is omitted. */
+export function diffBackups(from: string, to?: string, passphrase?: string) {
+  return invoke<BackupDiff>("diff_backups", { from, to, passphrase });
+}
+
+/** Encrypted backups stay sealed in the archive. */
+export function exportBackup(backupId: string, destination: string) {
+  return invoke<string>("export_backup", { backupId, destination });
+}
+
+/** `passphrase` opens an archive exported with another backup key. */
+export function importBackup(archive: string, passphrase?: string) {
+  return invoke<BackupInfo>("import_backup", { archive, passphrase });
+}
+
+export function pinBackup(backupId: string, pinned: boolean) {
+  return invoke<void>("pin_backup", { backupId, pinned });
+}
+
+export function pruneBackups(dryRun = false) {
+  return invoke<PruneResult>("prune_backups", { dryRun });
+}
+
+export function getSettings() {
+  return invoke<Settings>("get_settings");
+}
+
+export function saveSettings(settings: Settings) {
+  return invoke<Settings>("save_settings", { settings });
+}
+
+export function enableBackupEncryption(passphrase: string) {
+  return invoke<void>("enable_backup_encryption", { passphrase });
+}
+
+export function disableBackupEncryption(passphrase: string) {
+  return invoke<void>("disable_backup_encryption", { passphrase });
+}
+
+export function getRetentionPolicy() {
+  return invoke<RetentionPolicy>("get_retention_policy");
+}
+
+export function saveRetentionPolicy(policy: RetentionPolicy) {
+  return invoke<RetentionPolicy>("save_retention_policy", { policy });
+}
+
+export function getMapping() {
+  return invoke<MappingConfig>("get_mapping");
+}
+
+export function saveMapping(mapping: MappingConfig) {
+  return invoke<MappingConfig>("save_mapping", { mapping });
+}
+
+export function resetCategoryMapping(category: string) {
+  return invoke<MappingConfig>("reset_category_mapping", { category });
+}
+
+export function validateMapping(mapping: MappingConfig) {
+  return invoke<MappingValidation>("validate_mapping", { mapping });
+}
+
+export function addCategory(category: string, syncMode?: CategoryMapping["sync_mode"]) {
+  return invoke<MappingConfig>("add_category", { category, syncMode });
+}
+
+export function renameCategory(from: string, to: string) {
+  return invoke<MappingConfig>("rename_category", { from, to });
+}
+
+export function removeCategory(category: string, prune?: boolean) {
+  return invoke<MappingConfig>("remove_category", { category, prune });
 }
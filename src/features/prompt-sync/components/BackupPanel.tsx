@@ -2,14 +2,31 @@ import { useState } from "react";
 import { AnimatePresence, motion } from "framer-motion";
 import {
   Archive,
+  Camera,
   ChevronDown,
   Eye,
+  Eraser,
+  Lock,
+  LockOpen,
+  Pin,
+  PinOff,
   RefreshCw,
   RotateCcw,
+  ShieldCheck,
+  Tag,
   Trash2,
 } from "lucide-react";
-import type { BackupDetail, BackupInfo } from "@/types";
-import { getBackupDetail } from "@/api";
+import type {
+  BackupAnnotation,
+  BackupCompression,
+  BackupDetail,
+  BackupInfo,
+  BackupQuery,
+  PruneResult,
+  RestoreFilter,
+  RestorePreview,
+} from "@/types";
+import { getBackupDetail, previewRestore } from "@/api";
 import {
   Badge,
   Button,
@@ -24,19 +41,77 @@ import {
   DialogFooter,
   DialogHeader,
   DialogTitle,
+  Textarea,
 } from "@/components/ui";
-import { formatUnixMs } from "../utils/constants";
+import { formatBytes, formatUnixMs } from "../utils/constants";
 const MIN_REFRESH_FEEDBACK_MS = 550;
+const TRIGGERS = ["sync", "pull", "prune", "rollback", "restore", "snapshot"];
+const INPUT_CLASS =
+  "h-8 rounded-lg border border-black/10 bg-white/78 px-2.5 text-xs text-zinc-800 placeholder:text-zinc-400 focus-visible:outline-none focus-visible:ring-2 focus-visible:ring-black/30";
+
+/** Asks for the backup passphrase; `then` runs once it has been entered. */
+interface PassphraseDraft {
+  mode: "unlock" | "enable" | "disable";
+  value: string;
+  confirm: string;
+  then?: (passphrase: string) => void;
+}
+
+const PASSPHRASE_TITLES = {
+  unlock: "输入备份口令",
+  enable: "加密备份",
+  disable: "取消加密",
+} as const;
+
+const PASSPHRASE_DESCRIPTIONS = {
+  unlock: "备份已加密，查看或恢复前需要输入口令。口令仅在本次运行期间保留。",
+  enable: "设置口令后，所有备份内容都会被加密，同步无需口令。忘记口令将无法恢复备份。",
+  disable: "输入口令以解密所有备份。",
+} as const;
+
+/** The snapshot being created (`id` null) or the backup being annotated. */
+interface AnnotationDraft {
+  id: string | null;
+  label: string;
+  note: string;
+  tags: string;
+}
 
 export function BackupPanel({
   backupItems,
+  query,
+  compression,
+  onCompressionChange,
+  encrypted,
+  passphrase,
+  onUnlock,
+  onEncryptionChange,
+  onSearch,
   onRestore,
   onDelete,
+  onPin,
+  onPrune,
+  onVerify,
+  onSnapshot,
+  onAnnotate,
   onRefresh,
 }: {
   backupItems: BackupInfo[];
-  onRestore: (id: string) => void;
+  query: BackupQuery;
+  compression: BackupCompression;
+  onCompressionChange: (compression: BackupCompression) => void;
+  encrypted: boolean;
+  passphrase?: string;
+  onUnlock: (passphrase: string) => void;
+  onEncryptionChange: (enable: boolean, passphrase: string) => void;
+  onSearch: (query: BackupQuery) => void;
+  onRestore: (id: string, filter?: RestoreFilter) => void;
   onDelete: (id: string) => void;
+  onPin: (id: string, pinned: boolean) => void;
+  onPrune: (dryRun: boolean) => Promise<PruneResult | null>;
+  onVerify: (passphrase?: string) => void;
+  onSnapshot: (annotation: BackupAnnotation) => void;
+  onAnnotate: (id: string, annotation: BackupAnnotation) => void;
   onRefresh: () => Promise<void> | void;
 }) {
   const [detail, setDetail] = useState<BackupDetail | null>(null);
@@ -45,16 +120,42 @@ export function BackupPanel({
   const [confirmState, setConfirmState] = useState<{
     type: "restore" | "delete";
     id: string;
+    filter?: RestoreFilter;
   } | null>(null);
+  const [restorePlan, setRestorePlan] = useState<RestorePreview | null>(null);
+  const [prunePreview, setPrunePreview] = useState<PruneResult | null>(null);
+  const [draft, setDraft] = useState<AnnotationDraft | null>(null);
+  const [passphraseDraft, setPassphraseDraft] = useState<PassphraseDraft | null>(null);
 
-  async function onView(id: string) {
+  /** Runs `action` with the passphrase, asking for it first when needed. */
+  function withPassphrase(action: (passphrase?: string) => void) {
+    if (!encrypted || passphrase) {
+      action(passphrase);
+      return;
+    }
+    setPassphraseDraft({ mode: "unlock", value: "", confirm: "", then: action });
+  }
+
+  function onConfirmPassphrase() {
+    if (!passphraseDraft) return;
+    const { mode, value, then } = passphraseDraft;
+    setPassphraseDraft(null);
+    if (mode === "unlock") {
+      onUnlock(value);
+      then?.(value);
+      return;
+    }
+    onEncryptionChange(mode === "enable", value);
+  }
+
+  async function onView(id: string, secret = passphrase) {
     if (detail?.backup_id === id) {
       setDetail(null);
       return;
     }
     setLoadingId(id);
     try {
-      const next = await getBackupDetail(id);
+      const next = await getBackupDetail(id, secret);
       setDetail(next);
     } catch (error) {
       console.error(error);
@@ -63,17 +164,63 @@ export function BackupPanel({
     }
   }
 
+  async function openRestore(id: string, filter?: RestoreFilter, secret = passphrase) {
+    setRestorePlan(null);
+    setConfirmState({ type: "restore", id, filter });
+    try {
+      setRestorePlan(await previewRestore(id, filter, secret));
+    } catch (error) {
+      console.error(error);
+    }
+  }
+
   function onConfirmAction() {
     if (!confirmState) return;
-    const { type, id } = confirmState;
+    const { type, id, filter } = confirmState;
     setConfirmState(null);
     if (type === "restore") {
-      onRestore(id);
+      onRestore(id, filter);
       return;
     }
     onDelete(id);
   }
 
+  async function handlePrunePreview() {
+    const preview = await onPrune(true);
+    if (preview) {
+      setPrunePreview(preview);
+    }
+  }
+
+  function onConfirmPrune() {
+    setPrunePreview(null);
+    void onPrune(false);
+  }
+
+  function openAnnotate(item: BackupInfo) {
+    setDraft({
+      id: item.backup_id,
+      label: item.label ?? "",
+      note: item.note ?? "",
+      tags: item.tags.join(", "),
+    });
+  }
+
+  function onConfirmDraft() {
+    if (!draft) return;
+    const annotation: BackupAnnotation = {
+      label: draft.label,
+      note: draft.note,
+      tags: draft.tags.split(",").map((tag) => tag.trim()).filter(Boolean),
+    };
+    setDraft(null);
+    if (draft.id) {
+      onAnnotate(draft.id, annotation);
+    } else {
+      onSnapshot(annotation);
+    }
+  }
+
   async function handleRefresh() {
     if (isRefreshing) return;
     setIsRefreshing(true);
@@ -99,17 +246,96 @@ export function BackupPanel({
             <CardTitle>备份历史</CardTitle>
             <CardDescription>同步操作会自动生成快照，可回滚到任意历史版本。</CardDescription>
           </div>
-          <Button
-            variant="secondary"
-            size="sm"
-            onClick={() => void handleRefresh()}
-            disabled={isRefreshing}
-          >
-            <RefreshMotionIcon active={isRefreshing} className="size-3.5" />
-            刷新
-          </Button>
+          <div className="flex items-center gap-1.5">
+            <Button
+              variant="secondary"
+              size="sm"
+              onClick={() => setDraft({ id: null, label: "", note: "", tags: "" })}
+            >
+              <Camera className="size-3.5" />
+              快照
+            </Button>
+            <Button
+              variant="secondary"
+              size="sm"
+              onClick={() =>
+                setPassphraseDraft({
+                  mode: encrypted ? "disable" : "enable",
+                  value: "",
+                  confirm: "",
+                })
+              }
+            >
+              {encrypted ? <LockOpen className="size-3.5" /> : <Lock className="size-3.5" />}
+              {encrypted ? "取消加密" : "加密"}
+            </Button>
+            <Button variant="secondary" size="sm" onClick={() => withPassphrase(onVerify)}>
+              <ShieldCheck className="size-3.5" />
+              校验
+            </Button>
+            <Button variant="secondary" size="sm" onClick={() => void handlePrunePreview()}>
+              <Eraser className="size-3.5" />
+              清理
+            </Button>
+            <Button
+              variant="secondary"
+              size="sm"
+              onClick={() => void handleRefresh()}
+              disabled={isRefreshing}
+            >
+              <RefreshMotionIcon active={isRefreshing} className="size-3.5" />
+              刷新
+            </Button>
+          </div>
         </CardHeader>
         <CardContent className="pt-4">
+          <div className="mb-3 flex flex-wrap items-center gap-2">
+            <input
+              className={`${INPUT_CLASS} min-w-40 flex-1`}
+              placeholder="搜索标签或备注"
+              defaultValue={query.label ?? ""}
+              onKeyDown={(event) => {
+                if (event.key === "Enter") {
+                  onSearch({ ...query, label: event.currentTarget.value.trim() || undefined });
+                }
+              }}
+            />
+            <input
+              className={`${INPUT_CLASS} w-28`}
+              placeholder="tag"
+              defaultValue={query.tag ?? ""}
+              onKeyDown={(event) => {
+                if (event.key === "Enter") {
+                  onSearch({ ...query, tag: event.currentTarget.value.trim() || undefined });
+                }
+              }}
+            />
+            <select
+              className={INPUT_CLASS}
+              value={query.trigger ?? ""}
+              onChange={(event) =>
+                onSearch({ ...query, trigger: event.target.value || undefined })
+              }
+            >
+              <option value="">全部类型</option>
+              {TRIGGERS.map((trigger) => (
+                <option key={trigger} value={trigger}>
+                  {trigger}
+                </option>
+              ))}
+            </select>
+            <select
+              className={INPUT_CLASS}
+              title="新备份的压缩方式"
+              value={compression}
+              onChange={(event) =>
+                onCompressionChange(event.target.value as BackupCompression)
+              }
+            >
+              <option value="none">不压缩</option>
+              <option value="gzip">gzip 压缩</option>
+            </select>
+          </div>
           {backupItems.length === 0 ? (
             <EmptyState />
           ) : (
@@ -125,17 +351,41 @@ export function BackupPanel({
                       <div className="min-w-0 flex-1">
                         <p className="truncate font-mono text-xs font-semibold text-zinc-800">
                           {item.backup_id}
+                          {item.label && (
+                            <span className="ml-2 font-sans text-zinc-700">{item.label}</span>
+                          )}
+                          {item.pinned && (
+                            <Badge variant="muted" className="ml-2">
+                              已固定
+                            </Badge>
+                          )}
+                          {item.tags.map((tag) => (
+                            <Badge key={tag} variant="muted" className="ml-1.5">
+                              {tag}
+                            </Badge>
+                          ))}
                         </p>
                         <p className="mt-1 text-xs text-zinc-500">
-                          {formatUnixMs(item.created_at)} · {item.trigger} · {item.entry_count} 个文件
+                          {formatUnixMs(item.created_at)} · {item.trigger}
+                          {item.restored_from && ` (恢复 ${item.restored_from} 前)`} · {item.entry_count} 个文件 ·{" "}
+                          {formatBytes(item.total_bytes)}
+                          {item.stored_bytes !== item.total_bytes &&
+                            `（占用 ${formatBytes(item.stored_bytes)}）`}
                         </p>
+                        {item.note && (
+                          <p className="mt-1 whitespace-pre-wrap text-xs text-zinc-600">
+                            {item.note}
+                          </p>
+                        )}
                       </div>
                       <div className="flex items-center gap-1.5">
                         <Button
                           variant="secondary"
                           size="sm"
                           disabled={loadingId === item.backup_id}
-                          onClick={() => void onView(item.backup_id)}
+                          onClick={() =>
+                            withPassphrase((secret) => void onView(item.backup_id, secret))
+                          }
                         >
                           <Eye className="size-3.5" />
                           {loadingId === item.backup_id
@@ -144,14 +394,33 @@ export function BackupPanel({
                               ? "收起"
                               : "查看"}
                         </Button>
+                        <Button
+                          variant="secondary"
+                          size="sm"
+                          onClick={() => onPin(item.backup_id, !item.pinned)}
+                        >
+                          {item.pinned ? (
+                            <PinOff className="size-3.5" />
+                          ) : (
+                            <Pin className="size-3.5" />
+                          )}
+                          {item.pinned ? "取消固定" : "固定"}
+                        </Button>
+                        <Button
+                          variant="secondary"
+                          size="sm"
+                          onClick={() => openAnnotate(item)}
+                        >
+                          <Tag className="size-3.5" />
+                          标注
+                        </Button>
                         <Button
                           variant="secondary"
                           size="sm"
                           onClick={() =>
-                            setConfirmState({
-                              type: "restore",
-                              id: item.backup_id,
-                            })
+                            withPassphrase(
+                              (secret) => void openRestore(item.backup_id, undefined, secret),
+                            )
                           }
                         >
                           <RotateCcw className="size-3.5" />
@@ -183,7 +452,15 @@ export function BackupPanel({
                           transition={{ duration: 0.2 }}
                           className="overflow-hidden"
                         >
-                          <BackupDetailView detail={detail} />
+                          <BackupDetailView
+                            detail={detail}
+                            onRestoreEntry={(agent, path) =>
+                              void openRestore(item.backup_id, {
+                                agents: [agent],
+                                paths: [path],
+                              })
+                            }
+                          />
                         </motion.div>
                       )}
                     </AnimatePresence>
@@ -203,10 +480,13 @@ export function BackupPanel({
             </DialogTitle>
             <DialogDescription>
               {confirmState?.type === "restore"
-                ? `将恢复备份 ${confirmState.id} 的内容，是否继续？`
+                ? confirmState.filter
+                  ? `将从备份 ${confirmState.id} 恢复所选文件，是否继续？`
+                  : `将恢复备份 ${confirmState.id} 的内容，是否继续？`
                 : `将删除备份 ${confirmState?.id}，删除后无法恢复，是否继续？`}
             </DialogDescription>
           </DialogHeader>
+          {confirmState?.type === "restore" && <RestorePlanList plan={restorePlan} />}
           <DialogFooter>
             <Button variant="ghost" onClick={() => setConfirmState(null)}>
               取消
@@ -220,6 +500,137 @@ export function BackupPanel({
           </DialogFooter>
         </DialogContent>
       </Dialog>
+
+      <Dialog
+        open={prunePreview !== null}
+        onOpenChange={(open) => !open && setPrunePreview(null)}
+      >
+        <DialogContent>
+          <DialogHeader>
+            <DialogTitle>清理备份</DialogTitle>
+            <DialogDescription>
+              {prunePreview?.pruned.length
+                ? `按保留策略将删除 ${prunePreview.pruned.length} 个备份，保留 ${prunePreview.kept.length} 个（已固定的备份不会被删除），是否继续？`
+                : "按当前保留策略没有需要清理的备份。"}
+            </DialogDescription>
+          </DialogHeader>
+          {prunePreview && prunePreview.pruned.length > 0 && (
+            <ul className="max-h-48 space-y-1 overflow-auto font-mono text-xs text-zinc-600">
+              {prunePreview.pruned.map((item) => (
+                <li key={item.backup_id}>
+                  {item.backup_id} · {formatUnixMs(item.created_at)} · {item.trigger}
+                </li>
+              ))}
+            </ul>
+          )}
+          <DialogFooter>
+            <Button variant="ghost" onClick={() => setPrunePreview(null)}>
+              取消
+            </Button>
+            {prunePreview && prunePreview.pruned.length > 0 && (
+              <Button variant="danger" onClick={onConfirmPrune}>
+                确认清理
+              </Button>
+            )}
+          </DialogFooter>
+        </DialogContent>
+      </Dialog>
+
+      <Dialog
+        open={passphraseDraft !== null}
+        onOpenChange={(open) => !open && setPassphraseDraft(null)}
+      >
+        <DialogContent>
+          <DialogHeader>
+            <DialogTitle>{passphraseDraft && PASSPHRASE_TITLES[passphraseDraft.mode]}</DialogTitle>
+            <DialogDescription>
+              {passphraseDraft && PASSPHRASE_DESCRIPTIONS[passphraseDraft.mode]}
+            </DialogDescription>
+          </DialogHeader>
+          {passphraseDraft && (
+            <div className="space-y-2">
+              <input
+                type="password"
+                className={`${INPUT_CLASS} w-full`}
+                placeholder="口令"
+                autoFocus
+                value={passphraseDraft.value}
+                onChange={(event) =>
+                  setPassphraseDraft({ ...passphraseDraft, value: event.target.value })
+                }
+              />
+              {passphraseDraft.mode === "enable" && (
+                <input
+                  type="password"
+                  className={`${INPUT_CLASS} w-full`}
+                  placeholder="再次输入口令（至少 8 个字符）"
+                  value={passphraseDraft.confirm}
+                  onChange={(event) =>
+                    setPassphraseDraft({ ...passphraseDraft, confirm: event.target.value })
+                  }
+                />
+              )}
+            </div>
+          )}
+          <DialogFooter>
+            <Button variant="ghost" onClick={() => setPassphraseDraft(null)}>
+              取消
+            </Button>
+            <Button
+              variant={passphraseDraft?.mode === "disable" ? "danger" : "default"}
+              disabled={
+                !passphraseDraft?.value ||
+                (passphraseDraft.mode === "enable" &&
+                  passphraseDraft.value !== passphraseDraft.confirm)
+              }
+              onClick={onConfirmPassphrase}
+            >
+              确认
+            </Button>
+          </DialogFooter>
+        </DialogContent>
+      </Dialog>
+
+      <Dialog open={draft !== null} onOpenChange={(open) => !open && setDraft(null)}>
+        <DialogContent>
+          <DialogHeader>
+            <DialogTitle>{draft?.id ? "标注备份" : "创建快照"}</DialogTitle>
+            <DialogDescription>
+              {draft?.id
+                ? `修改备份 ${draft.id} 的标签、备注和 tag，留空即清除。`
+                : "备份所有映射目标和整个 source 目录，可随时恢复。"}
+            </DialogDescription>
+          </DialogHeader>
+          {draft && (
+            <div className="space-y-2">
+              <input
+                className={`${INPUT_CLASS} w-full`}
+                placeholder="标签，例如：升级前"
+                value={draft.label}
+                onChange={(event) => setDraft({ ...draft, label: event.target.value })}
+              />
+              <Textarea
+                className="min-h-20 font-sans text-xs"
+                placeholder="备注"
+                value={draft.note}
+                onChange={(event) => setDraft({ ...draft, note: event.target.value })}
+              />
+              <input
+                className={`${INPUT_CLASS} w-full`}
+                placeholder="tag，以逗号分隔"
+                value={draft.tags}
+                onChange={(event) => setDraft({ ...draft, tags: event.target.value })}
+              />
+            </div>
+          )}
+          <DialogFooter>
+            <Button variant="ghost" onClick={() => setDraft(null)}>
+              取消
+            </Button>
+            <Button onClick={onConfirmDraft}>{draft?.id ? "保存" : "创建快照"}</Button>
+          </DialogFooter>
+        </DialogContent>
+      </Dialog>
     </>
   );
 }
@@ -234,7 +645,38 @@ function EmptyState() {
   );
 }
 
-function BackupDetailView({ detail }: { detail: BackupDetail }) {
+const RESTORE_ACTION_LABELS = {
+  write: "覆盖",
+  delete: "删除",
+  none: "无变化",
+} as const;
+
+function RestorePlanList({ plan }: { plan: RestorePreview | null }) {
+  if (!plan) {
+    return <p className="text-xs text-zinc-500">正在计算恢复计划…</p>;
+  }
+  const changed = plan.entries.filter((entry) => entry.action !== "none");
+  if (changed.length === 0) {
+    return <p className="text-xs text-zinc-500">当前内容与备份一致，无需恢复。</p>;
+  }
+  return (
+    <ul className="max-h-48 space-y-1 overflow-auto font-mono text-xs text-zinc-600">
+      {changed.map((entry) => (
+        <li key={`${entry.agent}-${entry.target_relative_path}`}>
+          {RESTORE_ACTION_LABELS[entry.action]} · {entry.agent}/{entry.target_relative_path}
+        </li>
+      ))}
+    </ul>
+  );
+}
+
+function BackupDetailView({
+  detail,
+  onRestoreEntry,
+}: {
+  detail: BackupDetail;
+  onRestoreEntry: (agent: string, path: string) => void;
+}) {
   const [expandedIdx, setExpandedIdx] = useState<number | null>(null);
 
   return (
@@ -269,8 +711,25 @@ function BackupDetailView({ detail }: { detail: BackupDetail }) {
                   className="overflow-hidden"
                 >
                   <div className="bg-white/75 px-3 pb-3">
+                    <div className="flex justify-end py-2">
+                      <Button
+                        variant="secondary"
+                        size="sm"
+                        onClick={() => onRestoreEntry(entry.agent, entry.target_relative_path)}
+                      >
+                        <RotateCcw className="size-3.5" />
+                        仅恢复此文件
+                      </Button>
+                    </div>
+                    {entry.problem != null && (
+                      <p className="py-2 text-xs text-zinc-600">
+                        备份副本无法完整读取：{entry.problem}
+                      </p>
+                    )}
                     {entry.backup_content == null ? (
-                      <p className="py-2 text-xs text-zinc-500">该文件在同步前不存在</p>
+                      entry.problem == null && (
+                        <p className="py-2 text-xs text-zinc-500">该文件在同步前不存在</p>
+                      )
                     ) : (
                       <pre className="max-h-64 overflow-auto rounded-lg border border-black/10 bg-white/88 p-3 font-mono text-xs leading-relaxed whitespace-pre-wrap text-zinc-800">
                         {entry.backup_content}
@@ -57,8 +57,25 @@ export function PromptSyncLayout() {
     [applyRestoredPrompts, setStatusMessage],
   );
 
-  const { backupItems, refreshBackups, restoreBackupAction, deleteBackupAction } =
-    useBackups(setStatusMessage, setErrorMessage, { onRestored });
+  const {
+    backupItems,
+    backupQuery,
+    compression,
+    encrypted,
+    passphrase,
+    setPassphrase,
+    refreshBackups,
+    searchBackupsAction,
+    restoreBackupAction,
+    deleteBackupAction,
+    pinBackupAction,
+    pruneBackupsAction,
+    verifyBackupsAction,
+    setCompressionAction,
+    setEncryptionAction,
+    createSnapshotAction,
+    annotateBackupAction,
+  } = useBackups(setStatusMessage, setErrorMessage, { onRestored });
 
   const [view, setView] = useState<View>("prompts");
   const [isReloading, setIsReloading] = useState(false);
@@ -117,18 +134,48 @@ export function PromptSyncLayout() {
     if (!saved) return;
     try {
       const preview = await previewSync();
+      // Files edited directly in agent directories are never overwritten by quick sync.
+      const conflictCount = preview.items.filter((item) => item.status === "conflict").length;
+      const failedItems = preview.items.filter((item) => item.status === "error");
       const changedIds = preview.items
-        .filter((item) => item.status !== "unchanged")
+        .filter(
+          (item) =>
+            item.status !== "unchanged" && item.status !== "conflict" && item.status !== "error",
+        )
         .map((item) => item.id);
+      const conflictNote =
+        (conflictCount > 0 ? `，${conflictCount} 个文件在 Agent 目录中被手动修改，已跳过` : "") +
+        (failedItems.length > 0
+          ? `，${failedItems.length} 个文件渲染失败（${failedItems[0].id}: ${failedItems[0].error}）`
+          : "");
 
       if (changedIds.length === 0) {
-        setStatusMessage("所有文件已是最新，无需同步");
+        setStatusMessage(
+          conflictNote ? `没有可同步的文件${conflictNote}` : "所有文件已是最新，无需同步",
+        );
         return;
       }
 
-      const result = await applySync(changedIds, sourcePromptSnapshots);
+      const expectedHashes = Object.fromEntries(
+        preview.items.map((item) => [
+          item.id,
+          { before_hash: item.before_hash, after_hash: item.after_hash },
+        ]),
+      );
+      const result = await applySync(changedIds, sourcePromptSnapshots, undefined, expectedHashes);
+      if (result.failure) {
+        const { failure } = result;
+        setErrorMessage(
+          `同步失败（${failure.path ?? "未知文件"}: ${failure.error}），已回滚 ${failure.rolled_back.length} 个文件` +
+            (failure.rollback_errors.length > 0
+              ? `，${failure.rollback_errors.length} 个文件未能恢复，请从备份 ${result.backup_id} 手动恢复`
+              : ""),
+        );
+        await refreshBackups();
+        return;
+      }
       setStatusMessage(
-        `同步完成，${result.applied_count} 个文件已更新${result.backup_id ? `（备份 ${result.backup_id}）` : ""}`,
+        `同步完成，${result.applied_count} 个文件已更新${result.backup_id ? `（备份 ${result.backup_id}）` : ""}${conflictNote}`,
       );
       await refreshBackups();
     } catch (error) {
@@ -248,8 +295,21 @@ export function PromptSyncLayout() {
           <TabsContent value="backups">
             <BackupPanel
               backupItems={backupItems}
-              onRestore={(id) => void restoreBackupAction(id)}
+              query={backupQuery}
+              compression={compression}
+              onCompressionChange={(next) => void setCompressionAction(next)}
+              encrypted={encrypted}
+              passphrase={passphrase}
+              onUnlock={setPassphrase}
+              onEncryptionChange={(enable, secret) => void setEncryptionAction(enable, secret)}
+              onSearch={(query) => void searchBackupsAction(query)}
+              onRestore={(id, filter) => void restoreBackupAction(id, filter)}
               onDelete={(id) => void deleteBackupAction(id)}
+              onPin={(id, pinned) => void pinBackupAction(id, pinned)}
+              onPrune={pruneBackupsAction}
+              onVerify={(secret) => void verifyBackupsAction(secret)}
+              onSnapshot={(annotation) => void createSnapshotAction(annotation)}
+              onAnnotate={(id, annotation) => void annotateBackupAction(id, annotation)}
               onRefresh={() => refreshBackups()}
             />
           </TabsContent>
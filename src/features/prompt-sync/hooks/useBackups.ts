@@ -1,6 +1,27 @@
 import { useState, useCallback } from "react";
-import { listBackups, restoreBackup, deleteBackup } from "@/api";
-import type { BackupInfo, RestoreResult } from "@/types";
+import {
+  annotateBackup,
+  createSnapshot,
+  deleteBackup,
+  disableBackupEncryption,
+  enableBackupEncryption,
+  getSettings,
+  pinBackup,
+  pruneBackups,
+  restoreBackup,
+  saveSettings,
+  searchBackups,
+  verifyAllBackups,
+} from "@/api";
+import type {
+  BackupAnnotation,
+  BackupCompression,
+  BackupInfo,
+  BackupQuery,
+  PruneResult,
+  RestoreFilter,
+  RestoreResult,
+} from "@/types";
 
 interface UseBackupsOptions {
   onRestored?: (backupId: string, result: RestoreResult) => Promise<void> | void;
@@ -12,23 +33,43 @@ export function useBackups(
   options: UseBackupsOptions = {},
 ) {
   const [backupItems, setBackupItems] = useState<BackupInfo[]>([]);
+  const [backupQuery, setBackupQuery] = useState<BackupQuery>({});
+  const [compression, setCompression] = useState<BackupCompression>("none");
+  const [encrypted, setEncrypted] = useState(false);
+  // Kept in memory only, for the rest of the session.
+  const [passphrase, setPassphrase] = useState<string | undefined>();
   const { onRestored } = options;
 
-  const refreshBackups = useCallback(async () => {
-    try {
-      const items = await listBackups();
-      setBackupItems(items);
-    } catch (e) {
-      setErrorMessage(String(e));
-    }
-  }, [setErrorMessage]);
+  const refreshBackups = useCallback(
+    async (query: BackupQuery = backupQuery) => {
+      try {
+        const [items, settings] = await Promise.all([searchBackups(query), getSettings()]);
+        setBackupItems(items);
+        setCompression(settings.backup_compression);
+        setEncrypted(!!settings.backup_encryption);
+      } catch (e) {
+        setErrorMessage(String(e));
+      }
+    },
+    [backupQuery, setErrorMessage],
+  );
+
+  const searchBackupsAction = useCallback(
+    async (query: BackupQuery) => {
+      setBackupQuery(query);
+      await refreshBackups(query);
+    },
+    [refreshBackups],
+  );
 
   const restoreBackupAction = useCallback(
-    async (id: string) => {
+    async (id: string, filter?: RestoreFilter) => {
       try {
-        const r = await restoreBackup(id);
+        const r = await restoreBackup(id, filter, passphrase);
         setStatusMessage(
-          r.restored_count === 0 ? "恢复完成，当前内容与目标备份一致" : `恢复完成，${r.restored_count} 个文件`,
+          r.restored_count === 0
+            ? "恢复完成，当前内容与目标备份一致"
+            : `恢复完成，${r.restored_count} 个文件（恢复前的内容已备份为 ${r.backup_id}）`,
         );
         await refreshBackups();
         if (onRestored) {
@@ -38,7 +79,7 @@ export function useBackups(
         setErrorMessage(String(e));
       }
     },
-    [setStatusMessage, setErrorMessage, refreshBackups, onRestored],
+    [passphrase, setStatusMessage, setErrorMessage, refreshBackups, onRestored],
   );
 
   const deleteBackupAction = useCallback(
@@ -54,5 +95,131 @@ export function useBackups(
     [setStatusMessage, setErrorMessage, refreshBackups],
   );
 
-  return { backupItems, refreshBackups, restoreBackupAction, deleteBackupAction };
+  const pinBackupAction = useCallback(
+    async (id: string, pinned: boolean) => {
+      try {
+        await pinBackup(id, pinned);
+        setStatusMessage(pinned ? "备份已固定，不会被自动清理" : "已取消固定");
+        await refreshBackups();
+      } catch (e) {
+        setErrorMessage(String(e));
+      }
+    },
+    [setStatusMessage, setErrorMessage, refreshBackups],
+  );
+
+  const setCompressionAction = useCallback(
+    async (next: BackupCompression) => {
+      try {
+        const settings = await getSettings();
+        const saved = await saveSettings({ ...settings, backup_compression: next });
+        setCompression(saved.backup_compression);
+        setStatusMessage(
+          next === "none" ? "新备份将不再压缩" : `新备份将使用 ${next} 压缩`,
+        );
+      } catch (e) {
+        setErrorMessage(String(e));
+      }
+    },
+    [setStatusMessage, setErrorMessage],
+  );
+
+  const createSnapshotAction = useCallback(
+    async (annotation: BackupAnnotation) => {
+      try {
+        const info = await createSnapshot(annotation);
+        setStatusMessage(`已创建快照 ${info.backup_id}，${info.entry_count} 个文件`);
+        await refreshBackups();
+      } catch (e) {
+        setErrorMessage(String(e));
+      }
+    },
+    [setStatusMessage, setErrorMessage, refreshBackups],
+  );
+
+  const annotateBackupAction = useCallback(
+    async (id: string, annotation: BackupAnnotation) => {
+      try {
+        await annotateBackup(id, annotation);
+        setStatusMessage("备份标注已更新");
+        await refreshBackups();
+      } catch (e) {
+        setErrorMessage(String(e));
+      }
+    },
+    [setStatusMessage, setErrorMessage, refreshBackups],
+  );
+
+  const verifyBackupsAction = useCallback(async (secret = passphrase) => {
+    try {
+      const results = await verifyAllBackups(secret);
+      const damaged = results.filter((r) => !r.ok);
+      if (damaged.length === 0) {
+        setStatusMessage(`${results.length} 个备份校验通过`);
+      } else {
+        setErrorMessage(
+          `${damaged.length} 个备份已损坏，无法恢复：${damaged.map((r) => r.backup_id).join(", ")}`,
+        );
+      }
+    } catch (e) {
+      setErrorMessage(String(e));
+    }
+  }, [passphrase, setStatusMessage, setErrorMessage]);
+
+  const setEncryptionAction = useCallback(
+    async (enable: boolean, secret: string) => {
+      try {
+        if (enable) {
+          await enableBackupEncryption(secret);
+          setPassphrase(secret);
+          setStatusMessage("备份已加密，查看或恢复时需要输入口令");
+        } else {
+          await disableBackupEncryption(secret);
+          setPassphrase(undefined);
+          setStatusMessage("备份已解密");
+        }
+        await refreshBackups();
+      } catch (e) {
+        setErrorMessage(String(e));
+      }
+    },
+    [setStatusMessage, setErrorMessage, refreshBackups],
+  );
+
+  const pruneBackupsAction = useCallback(
+    async (dryRun: boolean): Promise<PruneResult | null> => {
+      try {
+        const r = await pruneBackups(dryRun);
+        if (!dryRun) {
+          setStatusMessage(`已清理 ${r.pruned.length} 个备份`);
+          await refreshBackups();
+        }
+        return r;
+      } catch (e) {
+        setErrorMessage(String(e));
+        return null;
+      }
+    },
+    [setStatusMessage, setErrorMessage, refreshBackups],
+  );
+
+  return {
+    backupItems,
+    backupQuery,
+    compression,
+    encrypted,
+    passphrase,
+    setPassphrase,
+    refreshBackups,
+    searchBackupsAction,
+    restoreBackupAction,
+    deleteBackupAction,
+    pinBackupAction,
+    pruneBackupsAction,
+    verifyBackupsAction,
+    setCompressionAction,
+    setEncryptionAction,
+    createSnapshotAction,
+    annotateBackupAction,
+  };
 }
@@ -49,11 +49,15 @@ export function usePromptEditors(
   }, []);
 
   const applyRestoredPrompts = useCallback((prompts: RestoredSourcePrompts) => {
+    const agent = (key: Exclude<EditorKey, "base">): EditorState => {
+      const content = prompts.agents[key] ?? "";
+      return { content, original: content };
+    };
     setEditors({
       base: { content: prompts.base, original: prompts.base },
-      codex: { content: prompts.codex, original: prompts.codex },
-      gemini: { content: prompts.gemini, original: prompts.gemini },
-      claude: { content: prompts.claude, original: prompts.claude },
+      codex: agent("codex"),
+      gemini: agent("gemini"),
+      claude: agent("claude"),
     });
   }, []);
 
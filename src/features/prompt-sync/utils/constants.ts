@@ -1,3 +1,14 @@
 export function formatUnixMs(ms: number) {
   return new Date(ms).toLocaleString();
 }
+
+export function formatBytes(bytes: number) {
+  const units = ["B", "KiB", "MiB", "GiB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return unit === 0 ? `${bytes} B` : `${value.toFixed(1)} ${units[unit]}`;
+}
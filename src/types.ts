@@ -1,4 +1,14 @@
-export type ScopeName = "source" | "codex" | "gemini" | "claude";
+/** `"source"` or the id of an agent from the registry (`agents.json`). */
+export type ScopeName = string;
+
+export interface AgentDefinition {
+  id: string;
+  display_name: string;
+  root_dir: string;
+  default_targets: Record<string, string>;
+  mcp_format?: "codex" | "gemini" | "claude";
+  command_format?: "markdown" | "toml";
+}
 
 export interface ScopeInfo {
   name: ScopeName;
@@ -9,8 +19,10 @@ export interface WorkspaceInfo {
   app_root: string;
   source_root: string;
   mapping_path: string;
+  agents_path: string;
   categories: string[];
   scopes: ScopeInfo[];
+  agents: AgentDefinition[];
 }
 
 export interface ScopeFiles {
@@ -27,10 +39,10 @@ export interface FileContent {
 }
 
 export interface CategoryMapping {
-  codex: string;
-  gemini: string;
-  claude: string;
-  sync_mode?: "replace" | "append";
+  targets: Record<string, string>;
+  sync_mode?: "replace" | "append" | "managed" | "mcp";
+  /** Render templates and `@include` lines; on by default for instructions only. */
+  templates?: boolean;
 }
 
 export interface MappingConfig {
@@ -38,6 +50,49 @@ export interface MappingConfig {
   categories: Record<string, CategoryMapping>;
 }
 
+export interface MappingFieldError {
+  category: string | null;
+  agent: string | null;
+  field: "version" | "category" | "target";
+  message: string;
+}
+
+export interface MappingTargetChange {
+  category: string;
+  agent: string;
+  before_target: string | null;
+  after_target: string | null;
+  before_sync_mode: CategoryMapping["sync_mode"] | null;
+  after_sync_mode: CategoryMapping["sync_mode"] | null;
+  before_templates: boolean | null;
+  after_templates: boolean | null;
+}
+
+export interface MappingFileChange {
+  agent: string;
+  category: string;
+  target_relative_path: string;
+  status: "create" | "update" | "append" | "delete" | "error";
+  orphaned: boolean;
+}
+
+export interface MappingValidation {
+  valid: boolean;
+  errors: MappingFieldError[];
+  changes: MappingTargetChange[];
+  files: MappingFileChange[];
+}
+
+export type ConflictResolution = "overwrite" | "skip" | "pull";
+
+export interface SyncConflict {
+  planned_status: "update" | "append" | "delete";
+  recorded_hash: string;
+  current_hash: string;
+  pull_target: string | null;
+  resolutions: ConflictResolution[];
+}
+
 export interface SyncItem {
   id: string;
   agent: ScopeName;
@@ -45,20 +100,72 @@ export interface SyncItem {
   source_file: string;
   target_relative_path: string;
   target_absolute_path: string;
-  status: "create" | "update" | "unchanged" | "append";
+  status:
+    | "create"
+    | "update"
+    | "unchanged"
+    | "append"
+    | "merge"
+    | "delete"
+    | "conflict"
+    | "error";
+  before_hash: string;
+  after_hash: string;
+  diff: string;
+  diff_stats: DiffStats;
+  conflict: SyncConflict | null;
+  /** Render failure (template, conversion or merge); set when status is "error". */
+  error: string | null;
+}
+
+export interface DiffStats {
+  added: number;
+  removed: number;
+  hunks: number;
+}
+
+export interface SyncItemContent {
+  id: string;
   before: string;
   after: string;
 }
 
+export interface SyncItemHashes {
+  before_hash: string;
+  after_hash: string;
+}
+
 export interface SyncPreview {
   generated_at: number;
   items: SyncItem[];
 }
 
+export interface PullSkip {
+  agent: string;
+  category: string;
+  target_relative_path: string;
+  reason: string;
+}
+
+/** Pull items read `source_file` from the agent and write `target_*` in source. */
+export interface PullPreview {
+  generated_at: number;
+  items: SyncItem[];
+  skipped: PullSkip[];
+}
+
+export interface ApplyFailure {
+  path: string | null;
+  error: string;
+  rolled_back: string[];
+  rollback_errors: string[];
+}
+
 export interface ApplySyncResult {
   backup_id: string | null;
   applied_count: number;
   files: string[];
+  failure: ApplyFailure | null;
 }
 
 export interface SourcePromptSnapshot {
@@ -72,17 +179,112 @@ export interface BackupInfo {
   created_at: number;
   trigger: string;
   entry_count: number;
+  pinned: boolean;
+  restored_from: string | null;
+  label: string | null;
+  note: string | null;
+  tags: string[];
+  total_bytes: number;
+  stored_bytes: number;
+}
+
+export interface BackupIssue {
+  agent: string;
+  target_relative_path: string;
+  problem: "missing" | "truncated" | "modified";
+}
+
+export interface BackupVerification {
+  backup_id: string;
+  ok: boolean;
+  checked: number;
+  issues: BackupIssue[];
+}
+
+export interface BackupQuery {
+  trigger?: string;
+  label?: string;
+  tag?: string;
+  agent?: string;
+  path?: string;
+}
+
+export interface BackupAnnotation {
+  label?: string;
+  note?: string;
+  tags?: string[];
+}
+
+export interface PruneResult {
+  dry_run: boolean;
+  pruned: BackupInfo[];
+  kept: BackupInfo[];
+}
+
+export type BackupCompression = "none" | "gzip";
+
+/** Only the public key and the passphrase-wrapped private key are stored. */
+export interface EncryptionSettings {
+  public_key: string;
+  salt: string;
+  wrapped_key: string;
+}
+
+export interface Settings {
+  backup_compression: BackupCompression;
+  backup_encryption?: EncryptionSettings;
+}
+
+export interface RetentionPolicy {
+  keep_last?: number;
+  keep_days?: number;
+  keep_daily?: number;
+  keep_weekly?: number;
+}
+
+export interface BackupDiffEntry {
+  agent: string;
+  target_relative_path: string;
+  /** `unrecorded`: only one side recorded the file, so it is not compared. */
+  status: "added" | "removed" | "modified" | "unrecorded";
+  diff: string;
+  diff_stats: DiffStats;
+}
+
+export interface BackupDiff {
+  from: string;
+  /** null when compared with what a sync would write now. */
+  to: string | null;
+  entries: BackupDiffEntry[];
 }
 
 export interface RestoredSourcePrompts {
   base: string;
-  codex: string;
-  gemini: string;
-  claude: string;
+  agents: Record<string, string>;
+}
+
+export interface RestoreFilter {
+  agents?: string[];
+  paths?: string[];
+  categories?: string[];
+}
+
+export interface RestorePlanEntry {
+  agent: string;
+  target_relative_path: string;
+  target_absolute_path: string;
+  category: string | null;
+  action: "write" | "delete" | "none";
+}
+
+export interface RestorePreview {
+  backup_id: string;
+  entries: RestorePlanEntry[];
 }
 
 export interface RestoreResult {
   restored_count: number;
+  backup_id: string | null;
   source_prompts: RestoredSourcePrompts;
 }
 
@@ -92,6 +294,7 @@ export interface BackupDetailEntry {
   existed_before: boolean;
   backup_content: string | null;
   current_content: string | null;
+  problem: string | null;
 }
 
 export interface BackupDetail {